@@ -0,0 +1,167 @@
+use std::sync::Mutex;
+
+use tokio_util::sync::CancellationToken;
+
+use crate::{error::AppError, models::UsageMetadata};
+
+/// Caps what one crawl may spend on AI requests.
+///
+/// Each request reserves the most it may use before it is sent and settles the
+/// reservation with the actual usage afterwards, so concurrent requests cannot overshoot
+/// the limits together. Once a request would exceed a limit the budget is exhausted and
+/// refuses every further request.
+#[derive(Default)]
+pub struct Budget {
+    max_cost_usd: Option<f64>,
+    max_tokens: Option<u64>,
+    state: Mutex<BudgetState>,
+    exhausted: CancellationToken,
+}
+
+#[derive(Default)]
+struct BudgetState {
+    spent: UsageMetadata,
+    reserved: UsageMetadata,
+}
+
+/// Usage held for a request that is in flight.
+#[must_use]
+pub struct Reservation(UsageMetadata);
+
+impl Budget {
+    pub fn new(max_cost_usd: Option<f64>, max_tokens: Option<u64>) -> Self {
+        Self {
+            max_cost_usd,
+            max_tokens,
+            ..Self::default()
+        }
+    }
+
+    /// Reserves `estimate`, or exhausts the budget if that would exceed a limit.
+    pub fn reserve(&self, estimate: UsageMetadata) -> Result<Reservation, AppError> {
+        if self.is_exhausted() {
+            return Err(AppError::BudgetExceeded(
+                "the crawl's budget is used up".to_string(),
+            ));
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let mut total = state.spent.clone();
+        total.add(&state.reserved);
+        total.add(&estimate);
+
+        if let Some(reason) = self.excess(&total) {
+            self.exhausted.cancel();
+            return Err(AppError::BudgetExceeded(reason));
+        }
+
+        state.reserved.add(&estimate);
+        Ok(Reservation(estimate))
+    }
+
+    /// Releases `reservation` and records what the request actually used, if it was sent.
+    pub fn settle(&self, reservation: Reservation, actual: Option<&UsageMetadata>) {
+        let mut state = self.state.lock().unwrap();
+        let reserved = &mut state.reserved;
+        reserved.input_tokens = reserved
+            .input_tokens
+            .saturating_sub(reservation.0.input_tokens);
+        reserved.output_tokens = reserved
+            .output_tokens
+            .saturating_sub(reservation.0.output_tokens);
+        reserved.total_cost = (reserved.total_cost - reservation.0.total_cost).max(0.0);
+
+        if let Some(actual) = actual {
+            state.spent.add(actual);
+        }
+    }
+
+    /// Tokens left under the token limit once spent and reserved usage is counted.
+    pub fn remaining_tokens(&self) -> Option<u64> {
+        let state = self.state.lock().unwrap();
+        let used = state.spent.input_tokens
+            + state.spent.output_tokens
+            + state.reserved.input_tokens
+            + state.reserved.output_tokens;
+        self.max_tokens.map(|max| max.saturating_sub(used))
+    }
+
+    /// Dollars left under the cost limit once spent and reserved usage is counted.
+    pub fn remaining_cost(&self) -> Option<f64> {
+        let state = self.state.lock().unwrap();
+        let used = state.spent.total_cost + state.reserved.total_cost;
+        self.max_cost_usd.map(|max| (max - used).max(0.0))
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted.is_cancelled()
+    }
+
+    /// Resolves once a request has been refused for exceeding the budget.
+    pub async fn exhausted(&self) {
+        self.exhausted.cancelled().await
+    }
+
+    fn excess(&self, total: &UsageMetadata) -> Option<String> {
+        let tokens = total.input_tokens + total.output_tokens;
+        match (self.max_cost_usd, self.max_tokens) {
+            (Some(max_cost), _) if total.total_cost > max_cost => Some(format!(
+                "${:.4} would exceed the ${:.4} limit",
+                total.total_cost, max_cost
+            )),
+            (_, Some(max_tokens)) if tokens > max_tokens => Some(format!(
+                "{} tokens would exceed the {} token limit",
+                tokens, max_tokens
+            )),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(tokens: u64, cost: f64) -> UsageMetadata {
+        UsageMetadata {
+            input_tokens: tokens,
+            output_tokens: 0,
+            total_cost: cost,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn reserves_until_a_limit_would_be_exceeded() {
+        let budget = Budget::new(Some(1.0), Some(1_000));
+
+        let first = budget.reserve(usage(400, 0.4)).unwrap();
+        let second = budget.reserve(usage(400, 0.4)).unwrap();
+        // Both reservations are still held, so a third request would go over.
+        assert!(matches!(
+            budget.reserve(usage(400, 0.1)),
+            Err(AppError::BudgetExceeded(_))
+        ));
+        assert!(budget.is_exhausted());
+
+        // Exhaustion sticks, even once reservations settle below their estimates.
+        budget.settle(first, Some(&usage(10, 0.01)));
+        budget.settle(second, None);
+        assert!(budget.reserve(usage(1, 0.0)).is_err());
+    }
+
+    #[test]
+    fn settles_reservations_with_actual_usage() {
+        let budget = Budget::new(None, Some(1_000));
+
+        let reservation = budget.reserve(usage(900, 0.0)).unwrap();
+        budget.settle(reservation, Some(&usage(100, 0.0)));
+        let reservation = budget.reserve(usage(800, 0.0)).unwrap();
+        budget.settle(reservation, Some(&usage(800, 0.0)));
+
+        assert_eq!(budget.remaining_tokens(), Some(100));
+        assert_eq!(budget.remaining_cost(), None);
+        assert!(budget.reserve(usage(101, 0.0)).is_err());
+        assert!(Budget::default().reserve(usage(u64::MAX / 4, 1e9)).is_ok());
+    }
+}
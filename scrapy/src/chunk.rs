@@ -0,0 +1,291 @@
+//! Splitting page content that is too large for one AI request, and merging the
+//! results extracted from each piece.
+
+use std::collections::HashSet;
+
+use scraper::{node::Node, ElementRef, Html};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{clean::escape_html, models::AiScrapingResult, schema::validate_records};
+
+/// Rough size of a token in bytes, used to estimate token counts without a tokenizer.
+const BYTES_PER_TOKEN: usize = 4;
+
+/// How page content is split across AI requests.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ChunkingOptions {
+    /// Estimated tokens of page content per request, excluding the prompt around it.
+    pub max_chunk_tokens: usize,
+    /// Requests in flight at once for the chunks of one page.
+    pub max_concurrent_chunks: usize,
+}
+
+impl Default for ChunkingOptions {
+    fn default() -> Self {
+        Self {
+            max_chunk_tokens: 30_000,
+            max_concurrent_chunks: 4,
+        }
+    }
+}
+
+pub fn estimate_tokens(text: &str) -> usize {
+    text.len().div_ceil(BYTES_PER_TOKEN)
+}
+
+/// Splits `content` into pieces of at most `max_tokens` estimated tokens. HTML is only
+/// cut between elements; an element too large on its own is split between its children,
+/// and each piece is wrapped in the element's tags. Other content is cut between
+/// paragraphs, then lines, then words.
+pub fn chunk_content(content: &str, html: bool, max_tokens: usize) -> Vec<String> {
+    let max_tokens = max_tokens.max(1);
+    if estimate_tokens(content) <= max_tokens {
+        return vec![content.to_string()];
+    }
+
+    if html {
+        let fragment = Html::parse_fragment(content);
+        chunk_children(fragment.root_element(), max_tokens)
+    } else {
+        split_text(content, max_tokens)
+    }
+}
+
+fn chunk_children(element: ElementRef, max_tokens: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+
+    for child in element.children() {
+        let (piece, child_element) = match child.value() {
+            Node::Text(text) => (escape_html(text), None),
+            Node::Element(_) => match ElementRef::wrap(child) {
+                Some(child) => (child.html(), Some(child)),
+                None => continue,
+            },
+            _ => continue,
+        };
+
+        if estimate_tokens(&piece) <= max_tokens {
+            if estimate_tokens(&current) + estimate_tokens(&piece) > max_tokens {
+                flush(&mut chunks, &mut current);
+            }
+            current.push_str(&piece);
+            continue;
+        }
+
+        flush(&mut chunks, &mut current);
+        match child_element {
+            Some(child) => {
+                let (open, close) = tags(child);
+                let inner_tokens =
+                    max_tokens.saturating_sub(estimate_tokens(&open) + estimate_tokens(&close));
+                for inner in chunk_children(child, inner_tokens.max(1)) {
+                    chunks.push(format!("{}{}{}", open, inner, close));
+                }
+            }
+            None => chunks.extend(split_text(&piece, max_tokens)),
+        }
+    }
+
+    flush(&mut chunks, &mut current);
+    chunks
+}
+
+fn tags(element: ElementRef) -> (String, String) {
+    let name = element.value().name();
+    let attributes: String = element
+        .value()
+        .attrs()
+        .map(|(attribute, value)| format!(" {}=\"{}\"", attribute, escape_html(value)))
+        .collect();
+    (format!("<{}{}>", name, attributes), format!("</{}>", name))
+}
+
+fn split_text(text: &str, max_tokens: usize) -> Vec<String> {
+    split_on(text, &["\n\n", "\n", " "], max_tokens)
+}
+
+fn split_on(text: &str, separators: &[&str], max_tokens: usize) -> Vec<String> {
+    if estimate_tokens(text) <= max_tokens {
+        return vec![text.to_string()];
+    }
+    let Some((separator, finer)) = separators.split_first() else {
+        return split_bytes(text, max_tokens * BYTES_PER_TOKEN);
+    };
+
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for part in text.split(separator) {
+        if estimate_tokens(part) > max_tokens {
+            flush(&mut chunks, &mut current);
+            chunks.extend(split_on(part, finer, max_tokens));
+            continue;
+        }
+        if !current.is_empty() {
+            if (current.len() + separator.len() + part.len()).div_ceil(BYTES_PER_TOKEN) > max_tokens
+            {
+                flush(&mut chunks, &mut current);
+            } else {
+                current.push_str(separator);
+            }
+        }
+        current.push_str(part);
+    }
+
+    flush(&mut chunks, &mut current);
+    chunks
+}
+
+/// Cuts `text` into pieces of at most `max_bytes`, on character boundaries.
+fn split_bytes(text: &str, max_bytes: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < text.len() {
+        let mut end = (start + max_bytes.max(1)).min(text.len());
+        while !text.is_char_boundary(end) {
+            end += 1;
+        }
+        chunks.push(text[start..end].to_string());
+        start = end;
+    }
+    chunks
+}
+
+fn flush(chunks: &mut Vec<String>, current: &mut String) {
+    if !current.trim().is_empty() {
+        chunks.push(std::mem::take(current));
+    }
+    current.clear();
+}
+
+/// Combines the results extracted from the chunks of one page, in chunk order: records
+/// are concatenated without duplicates and usage is summed. Records are revalidated
+/// against `schema` so that validation errors index the merged records.
+pub fn merge_results(
+    results: Vec<AiScrapingResult>,
+    schema: Option<&Value>,
+) -> Option<AiScrapingResult> {
+    if results.len() <= 1 {
+        return results.into_iter().next();
+    }
+
+    let mut results = results.into_iter();
+    let mut merged = results.next()?;
+    let mut records = Vec::new();
+    let mut seen = HashSet::new();
+
+    let mut add_records = |data: Value| {
+        let values = match data {
+            Value::Array(values) => values,
+            Value::Null => vec![],
+            value => vec![value],
+        };
+        for value in values {
+            if seen.insert(value.to_string()) {
+                records.push(value);
+            }
+        }
+    };
+
+    add_records(merged.data.take());
+    for result in results {
+        add_records(result.data);
+        merged.usage_metadata.add(&result.usage_metadata);
+        merged.start_time = merged.start_time.min(result.start_time);
+        merged.end_time = merged.end_time.max(result.end_time);
+    }
+
+    merged.validation_errors = schema
+        .map(|schema| validate_records(schema, &records))
+        .unwrap_or_default();
+    merged.data = Value::Array(records);
+    Some(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use serde_json::json;
+
+    use super::*;
+    use crate::models::UsageMetadata;
+
+    #[test]
+    fn splits_html_between_elements() {
+        let items: String = (0..6)
+            .map(|i| format!("<li>item number {}</li>", i))
+            .collect();
+        let content = format!("<h1>Title</h1><ul class=\"list\">{}</ul>", items);
+
+        let chunks = chunk_content(&content, true, 20);
+        assert_eq!(
+            chunks,
+            vec![
+                "<h1>Title</h1>",
+                "<ul class=\"list\"><li>item number 0</li><li>item number 1</li></ul>",
+                "<ul class=\"list\"><li>item number 2</li><li>item number 3</li></ul>",
+                "<ul class=\"list\"><li>item number 4</li><li>item number 5</li></ul>",
+            ]
+        );
+        assert_eq!(chunk_content(&content, true, 10_000), vec![content]);
+    }
+
+    #[test]
+    fn splits_text_between_paragraphs_lines_and_words() {
+        let content = "First paragraph.\n\nSecond one, a bit longer.\nWith two lines.";
+        let chunks = chunk_content(content, false, 8);
+        assert_eq!(
+            chunks,
+            vec![
+                "First paragraph.",
+                "Second one, a bit longer.",
+                "With two lines."
+            ]
+        );
+        assert!(chunks.iter().all(|chunk| estimate_tokens(chunk) <= 8));
+
+        let words = chunk_content("aaaa bbbb cccc dddd", false, 3);
+        assert_eq!(words, vec!["aaaa bbbb", "cccc dddd"]);
+    }
+
+    #[test]
+    fn merges_and_deduplicates_chunk_results() {
+        let result = |data: Value, input_tokens: u64| AiScrapingResult {
+            url: "https://example.com/".to_string(),
+            model: "gemini-1.5-flash-latest".to_string(),
+            start_time: Utc::now(),
+            end_time: Some(Utc::now()),
+            data,
+            usage_metadata: UsageMetadata {
+                input_tokens,
+                output_tokens: 10,
+                total_cost: 0.5,
+                ..Default::default()
+            },
+            validation_errors: vec![],
+            content_size: None,
+        };
+        let schema = json!({ "type": "object", "required": ["name"] });
+
+        let merged = merge_results(
+            vec![
+                result(json!([{ "name": "a" }, { "name": "b" }]), 100),
+                result(json!([{ "name": "b" }, { "title": "c" }]), 200),
+            ],
+            Some(&schema),
+        )
+        .unwrap();
+
+        assert_eq!(
+            merged.data,
+            json!([{ "name": "a" }, { "name": "b" }, { "title": "c" }])
+        );
+        assert_eq!(merged.usage_metadata.input_tokens, 300);
+        assert_eq!(merged.usage_metadata.output_tokens, 20);
+        assert_eq!(merged.usage_metadata.total_cost, 1.0);
+        assert_eq!(merged.validation_errors.len(), 1);
+        assert_eq!(merged.validation_errors[0].index, 2);
+    }
+}
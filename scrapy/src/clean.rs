@@ -0,0 +1,397 @@
+use scraper::{node::Node, ElementRef};
+use serde::{Deserialize, Serialize};
+
+/// Tags that never hold page content.
+const NON_CONTENT_TAGS: [&str; 13] = [
+    "script", "style", "noscript", "template", "svg", "canvas", "iframe", "object", "embed",
+    "link", "meta", "head", "title",
+];
+
+/// Page chrome removed when `remove_boilerplate` is set.
+const BOILERPLATE_TAGS: [&str; 5] = ["nav", "header", "footer", "aside", "dialog"];
+const BOILERPLATE_ROLES: [&str; 5] = ["navigation", "banner", "contentinfo", "dialog", "alert"];
+/// Matched against `class` and `id` values.
+const BOILERPLATE_MARKERS: [&str; 6] =
+    ["cookie", "consent", "advert", "newsletter", "popup", "gdpr"];
+
+/// Attributes kept in cleaned HTML.
+const KEPT_ATTRIBUTES: [&str; 4] = ["href", "src", "alt", "title"];
+const VOID_TAGS: [&str; 6] = ["br", "hr", "img", "input", "source", "wbr"];
+
+/// How page content is cleaned before it is sent to the AI model.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CleaningOptions {
+    /// Send the raw HTML when disabled.
+    pub enabled: bool,
+    /// Drop navigation, headers, footers, sidebars, cookie banners and hidden elements.
+    pub remove_boilerplate: bool,
+    pub format: ContentFormat,
+}
+
+impl CleaningOptions {
+    /// Whether `clean` returns HTML, as opposed to Markdown or text.
+    pub fn produces_html(&self) -> bool {
+        !self.enabled || self.format == ContentFormat::Html
+    }
+}
+
+impl Default for CleaningOptions {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            remove_boilerplate: true,
+            format: ContentFormat::Html,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ContentFormat {
+    /// HTML without non-content tags and with only `href`, `src`, `alt` and `title` attributes.
+    #[default]
+    Html,
+    Markdown,
+    /// Plain text, one block per line.
+    Text,
+}
+
+/// The content of `element`, cleaned according to `options`.
+pub fn clean(element: ElementRef, options: &CleaningOptions) -> String {
+    if !options.enabled {
+        return element.inner_html();
+    }
+
+    match options.format {
+        ContentFormat::Html => {
+            let mut out = String::new();
+            write_html_children(element, options, &mut out);
+            out.trim().to_string()
+        }
+        ContentFormat::Markdown | ContentFormat::Text => {
+            let mut writer = MarkdownWriter {
+                options,
+                markup: options.format == ContentFormat::Markdown,
+                out: String::new(),
+                lists: Vec::new(),
+            };
+            writer.children(element);
+            normalize_lines(&writer.out)
+        }
+    }
+}
+
+fn is_removed(element: ElementRef, options: &CleaningOptions) -> bool {
+    let element = element.value();
+    if NON_CONTENT_TAGS.contains(&element.name()) {
+        return true;
+    }
+    if !options.remove_boilerplate {
+        return false;
+    }
+
+    let marked = |value: &str| {
+        let value = value.to_ascii_lowercase();
+        BOILERPLATE_MARKERS
+            .iter()
+            .any(|marker| value.contains(marker))
+    };
+
+    BOILERPLATE_TAGS.contains(&element.name())
+        || element.attr("hidden").is_some()
+        || element.attr("aria-hidden") == Some("true")
+        || element
+            .attr("role")
+            .is_some_and(|role| BOILERPLATE_ROLES.contains(&role))
+        || element.classes().any(marked)
+        || element.id().is_some_and(marked)
+}
+
+fn write_html_children(element: ElementRef, options: &CleaningOptions, out: &mut String) {
+    for child in element.children() {
+        match child.value() {
+            Node::Text(text) => push_collapsed(out, text, escape_html),
+            Node::Element(_) => {
+                if let Some(child) = ElementRef::wrap(child) {
+                    write_html_element(child, options, out);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn write_html_element(element: ElementRef, options: &CleaningOptions, out: &mut String) {
+    if is_removed(element, options) {
+        return;
+    }
+
+    let name = element.value().name();
+    out.push('<');
+    out.push_str(name);
+    for (attribute, value) in element.value().attrs() {
+        if KEPT_ATTRIBUTES.contains(&attribute) {
+            out.push_str(&format!(" {}=\"{}\"", attribute, escape_html(value)));
+        }
+    }
+    out.push('>');
+
+    if VOID_TAGS.contains(&name) {
+        return;
+    }
+    write_html_children(element, options, out);
+    out.push_str(&format!("</{}>", name));
+}
+
+/// Appends `text` with runs of whitespace collapsed to a single space.
+fn push_collapsed(out: &mut String, text: &str, escape: fn(&str) -> String) {
+    if text.starts_with(char::is_whitespace)
+        && !out.is_empty()
+        && !out.ends_with(char::is_whitespace)
+    {
+        out.push(' ');
+    }
+    let words: Vec<&str> = text.split_whitespace().collect();
+    out.push_str(&escape(&words.join(" ")));
+    if !words.is_empty() && text.ends_with(char::is_whitespace) {
+        out.push(' ');
+    }
+}
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Renders Markdown, or plain text with the same block structure when `markup` is off.
+struct MarkdownWriter<'a> {
+    options: &'a CleaningOptions,
+    markup: bool,
+    out: String,
+    /// Open lists, innermost last: the next item number for ordered lists.
+    lists: Vec<Option<usize>>,
+}
+
+impl MarkdownWriter<'_> {
+    fn children(&mut self, element: ElementRef) {
+        for child in element.children() {
+            match child.value() {
+                Node::Text(text) => push_collapsed(&mut self.out, text, str::to_string),
+                Node::Element(_) => {
+                    if let Some(child) = ElementRef::wrap(child) {
+                        self.element(child);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn element(&mut self, element: ElementRef) {
+        if is_removed(element, self.options) {
+            return;
+        }
+
+        let name = element.value().name();
+        match name {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.block();
+                if self.markup {
+                    let level = name[1..].parse().unwrap_or(1);
+                    self.out.push_str(&format!("{} ", "#".repeat(level)));
+                }
+                self.children(element);
+                self.block();
+            }
+            "ul" | "ol" => {
+                self.block();
+                self.lists.push((name == "ol").then_some(1));
+                self.children(element);
+                self.lists.pop();
+                self.block();
+            }
+            "li" => {
+                self.line();
+                let depth = self.lists.len().saturating_sub(1);
+                self.out.push_str(&"  ".repeat(depth));
+                match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        self.out.push_str(&format!("{}. ", number));
+                        *number += 1;
+                    }
+                    _ if self.markup => self.out.push_str("- "),
+                    _ => {}
+                }
+                self.children(element);
+                self.line();
+            }
+            "pre" => {
+                self.block();
+                let code: String = element.text().collect();
+                if self.markup {
+                    self.out.push_str(&format!("```\n{}\n```", code.trim_end()));
+                } else {
+                    self.out.push_str(code.trim_end());
+                }
+                self.block();
+            }
+            "tr" => {
+                self.line();
+                self.children(element);
+                self.line();
+            }
+            "td" | "th" => {
+                self.out.push_str("| ");
+                self.children(element);
+                self.out.push(' ');
+            }
+            "br" => self.out.push('\n'),
+            "hr" => {
+                self.block();
+                if self.markup {
+                    self.out.push_str("---");
+                }
+                self.block();
+            }
+            "a" => match element.value().attr("href") {
+                Some(href) if self.markup => {
+                    self.out.push('[');
+                    self.children(element);
+                    self.out.push_str(&format!("]({})", href));
+                }
+                _ => self.children(element),
+            },
+            "img" => {
+                let alt = element.value().attr("alt").unwrap_or_default();
+                match element.value().attr("src") {
+                    Some(src) if self.markup => self.out.push_str(&format!("![{}]({})", alt, src)),
+                    _ => self.out.push_str(alt),
+                }
+            }
+            "strong" | "b" => self.wrap(element, "**"),
+            "em" | "i" => self.wrap(element, "*"),
+            "code" => self.wrap(element, "`"),
+            "p" | "div" | "section" | "article" | "main" | "header" | "footer" | "aside"
+            | "nav" | "blockquote" | "figure" | "figcaption" | "table" | "form" | "dl" | "dt"
+            | "dd" | "address" | "details" | "summary" => {
+                self.block();
+                self.children(element);
+                self.block();
+            }
+            _ => self.children(element),
+        }
+    }
+
+    fn wrap(&mut self, element: ElementRef, marker: &str) {
+        if self.markup {
+            self.out.push_str(marker);
+            self.children(element);
+            self.out.push_str(marker);
+        } else {
+            self.children(element);
+        }
+    }
+
+    /// Ends the current line.
+    fn line(&mut self) {
+        trim_end_spaces(&mut self.out);
+        if !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.out.push('\n');
+        }
+    }
+
+    /// Ends the current paragraph.
+    fn block(&mut self) {
+        self.line();
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+    }
+}
+
+fn trim_end_spaces(out: &mut String) {
+    let trimmed = out.trim_end_matches([' ', '\t']).len();
+    out.truncate(trimmed);
+}
+
+/// Trims every line and collapses runs of blank lines to one.
+fn normalize_lines(text: &str) -> String {
+    let mut lines: Vec<&str> = Vec::new();
+    for line in text.lines().map(str::trim_end) {
+        if line.trim().is_empty() && lines.last().is_none_or(|last| last.is_empty()) {
+            continue;
+        }
+        lines.push(if line.trim().is_empty() { "" } else { line });
+    }
+    lines.join("\n").trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use scraper::{Html, Selector};
+
+    use super::*;
+
+    const PAGE: &str = r#"<html><head><title>Shop</title><style>p { color: red }</style></head>
+        <body>
+            <nav><a href="/">Home</a></nav>
+            <div class="cookie-banner">We use cookies</div>
+            <main class="content" data-tracking="x1">
+                <h1 style="margin: 0">Desk   lamp</h1>
+                <p>Only <strong>$20</strong> at <a href="/lamp" onclick="track()">our shop</a>.</p>
+                <ul><li>Warm light</li><li>USB &amp; mains</li></ul>
+                <script>track()</script>
+                <svg><path d="M0 0"/></svg>
+            </main>
+            <footer>© Shop</footer>
+        </body></html>"#;
+
+    fn clean_body(options: CleaningOptions) -> String {
+        let document = Html::parse_document(PAGE);
+        let body = document
+            .select(&Selector::parse("body").unwrap())
+            .next()
+            .unwrap();
+        clean(body, &options)
+    }
+
+    #[test]
+    fn strips_non_content_and_boilerplate() {
+        let html = clean_body(CleaningOptions::default());
+        assert_eq!(
+            html,
+            "<main> <h1>Desk lamp</h1> <p>Only <strong>$20</strong> at <a href=\"/lamp\">our shop</a>.</p> <ul><li>Warm light</li><li>USB &amp; mains</li></ul> </main>"
+        );
+
+        let kept = clean_body(CleaningOptions {
+            remove_boilerplate: false,
+            ..CleaningOptions::default()
+        });
+        assert!(kept.contains("<nav>") && kept.contains("We use cookies"));
+        assert!(!kept.contains("script") && !kept.contains("svg"));
+    }
+
+    #[test]
+    fn converts_to_markdown_and_text() {
+        let markdown = clean_body(CleaningOptions {
+            format: ContentFormat::Markdown,
+            ..CleaningOptions::default()
+        });
+        assert_eq!(
+            markdown,
+            "# Desk lamp\n\nOnly **$20** at [our shop](/lamp).\n\n- Warm light\n- USB & mains"
+        );
+
+        let text = clean_body(CleaningOptions {
+            format: ContentFormat::Text,
+            ..CleaningOptions::default()
+        });
+        assert_eq!(
+            text,
+            "Desk lamp\n\nOnly $20 at our shop.\n\nWarm light\nUSB & mains"
+        );
+    }
+}
@@ -1,9 +1,71 @@
-use crate::models::PricingInfo;
+use crate::models::{LongContextPricing, PricingInfo};
 use phf::phf_map;
 
+/// User-agent sent with page and robots.txt requests when `user_agent` is not set.
+pub const DEFAULT_USER_AGENT: &str =
+    "scrapy/0.1 (+https://github.com/sabry-awad97/universal-web-scraper)";
+
+/// Built-in prices in USD per million tokens, used for models missing from the pricing
+/// config file (see `pricing`).
 pub static PRICING_INFO: phf::Map<&'static str, PricingInfo> = phf_map! {
+    "gemini-pro" => PricingInfo {
+        input: 0.5,
+        output: 1.5,
+        cached_input: None,
+        long_context: None,
+    },
+    "gemini-1.5-pro-latest" => PricingInfo {
+        input: 1.25,
+        output: 5.0,
+        cached_input: Some(0.3125),
+        long_context: Some(LongContextPricing {
+            above_input_tokens: 128_000,
+            input: 2.5,
+            output: 10.0,
+            cached_input: Some(0.625),
+        }),
+    },
     "gemini-1.5-flash-latest" => PricingInfo {
-        input: 0.075 / 1_000_000.0,
-        output: 0.3 / 1_000_000.0,
+        input: 0.075,
+        output: 0.3,
+        cached_input: Some(0.01875),
+        long_context: Some(LongContextPricing {
+            above_input_tokens: 128_000,
+            input: 0.15,
+            output: 0.6,
+            cached_input: Some(0.0375),
+        }),
+    },
+    "gemini-1.5-flash-8b-latest" => PricingInfo {
+        input: 0.0375,
+        output: 0.15,
+        cached_input: Some(0.01),
+        long_context: Some(LongContextPricing {
+            above_input_tokens: 128_000,
+            input: 0.075,
+            output: 0.3,
+            cached_input: Some(0.02),
+        }),
+    },
+    "openai:gpt-4o-mini" => PricingInfo {
+        input: 0.15,
+        output: 0.6,
+        cached_input: Some(0.075),
+        long_context: None,
+    },
+    "openai:gpt-4o" => PricingInfo {
+        input: 2.5,
+        output: 10.0,
+        cached_input: Some(1.25),
+        long_context: None,
     },
 };
+
+/// Redirects followed for a single page before giving up.
+pub const MAX_REDIRECTS: usize = 10;
+
+/// Pages followed through "next page" links when `max_pagination_pages` is not set.
+pub const DEFAULT_MAX_PAGINATION_PAGES: usize = 10;
+
+/// Links offered to the AI provider when resolving `pagination_details`.
+pub const MAX_PAGINATION_CANDIDATES: usize = 300;
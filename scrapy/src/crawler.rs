@@ -1,52 +1,72 @@
 use std::{
-    collections::HashSet,
+    collections::{HashSet, VecDeque},
     fmt::Display,
-    sync::{
-        atomic::{AtomicUsize, Ordering},
-        Arc,
-    },
+    sync::Arc,
     time::Duration,
 };
 
 use futures_util::StreamExt;
 use serde::Serialize;
-use tokio::{
-    sync::{mpsc, Barrier},
-    time::sleep,
-};
+use tokio::sync::{mpsc, Barrier, Semaphore};
 use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::CancellationToken;
 
-use crate::{models::ScrapeParams, spider::Spider};
+use crate::{
+    models::ScrapeParams, progress::ProgressReporter, scheduler::HostScheduler, spider::Spider,
+};
 
 pub struct Crawler {
     delay: Duration,
+    max_requests_per_host: usize,
     crawling_concurrency: usize,
     processing_concurrency: usize,
-    barrier: Arc<Barrier>,
-    active_spiders: Arc<AtomicUsize>,
+    fetch_slots: Arc<Semaphore>,
+    processing_slots: Arc<Semaphore>,
+}
+
+/// State that belongs to a single `crawl` call and is shared with its scraper and
+/// processor tasks, so that concurrent crawls on one `Crawler` never interfere.
+struct CrawlContext {
+    barrier: Barrier,
+    scheduler: HostScheduler,
+    progress: Arc<ProgressReporter>,
+    cancel: CancellationToken,
+    stop: CancellationToken,
 }
 
 impl Crawler {
+    /// `delay` is the default minimum interval between requests to the same host and
+    /// `max_requests_per_host` how many of them may be in flight at once; both can be
+    /// overridden per crawl through `ScrapeParams`. `crawling_concurrency` and
+    /// `processing_concurrency` cap fetches and processed items across all crawls
+    /// running on this crawler at the same time.
     pub fn new(
         delay: Duration,
+        max_requests_per_host: usize,
         crawling_concurrency: usize,
         processing_concurrency: usize,
     ) -> Self {
-        let barrier = Arc::new(Barrier::new(3));
-        let active_spiders = Arc::new(AtomicUsize::new(0));
         Self {
             delay,
+            max_requests_per_host,
             crawling_concurrency,
             processing_concurrency,
-            barrier,
-            active_spiders,
+            fetch_slots: Arc::new(Semaphore::new(crawling_concurrency.max(1))),
+            processing_slots: Arc::new(Semaphore::new(processing_concurrency.max(1))),
         }
     }
 
+    /// Crawls until every reachable URL has been scraped and processed, or until `stop`
+    /// or `cancel` is triggered. Stopping stops queueing, abandons in-flight requests and
+    /// drains the remaining queue, but lets items that are already being processed finish.
+    /// Cancelling abandons those too. `stop` must be `cancel` or a child token of it.
     pub async fn crawl<T, E>(
         &self,
         spider: Arc<dyn Spider<Item = T, Error = E>>,
         params: ScrapeParams,
+        progress: Arc<ProgressReporter>,
+        cancel: CancellationToken,
+        stop: CancellationToken,
     ) where
         T: Serialize + Send + 'static,
         E: Display + Send + 'static,
@@ -55,118 +75,426 @@ impl Crawler {
         let crawling_queue_capacity = self.crawling_concurrency * 400;
         let processing_queue_capacity = self.processing_concurrency * 10;
 
-        let (urls_to_visit_tx, urls_to_visit_rx) = mpsc::channel::<String>(crawling_queue_capacity);
+        let max_depth = params.max_depth;
+        let max_pages = params.max_pages;
+
+        let scheduler = HostScheduler::new(
+            params
+                .request_interval_ms
+                .map(Duration::from_millis)
+                .unwrap_or(self.delay),
+            params
+                .max_requests_per_host
+                .unwrap_or(self.max_requests_per_host),
+            self.fetch_slots.clone(),
+        );
+        let context = Arc::new(CrawlContext {
+            barrier: Barrier::new(3),
+            scheduler,
+            progress: progress.clone(),
+            cancel,
+            stop: stop.clone(),
+        });
+
+        let (urls_to_visit_tx, urls_to_visit_rx) =
+            mpsc::channel::<(String, usize)>(crawling_queue_capacity);
         let (items_tx, items_rx) = mpsc::channel(processing_queue_capacity);
         let (new_urls_tx, mut new_urls_rx) = mpsc::channel(crawling_queue_capacity);
 
-        for url in spider.start_urls() {
-            visited_urls.insert(url.clone());
-            let _ = urls_to_visit_tx.send(url).await;
-        }
-
-        self.launch_processors(spider.clone(), items_rx);
+        self.launch_processors(spider.clone(), items_rx, context.clone());
 
         self.launch_scrapers(
             spider.clone(),
             urls_to_visit_rx,
-            new_urls_tx.clone(),
+            new_urls_tx,
             items_tx,
-            params,
+            context.clone(),
         );
 
-        loop {
-            if let Ok((visited_url, new_urls)) = new_urls_rx.try_recv() {
-                visited_urls.insert(visited_url);
+        // URLs waiting to be handed to the scrapers. Keeping them here rather than in the
+        // bounded channel means the coordinator never blocks on a full queue while the
+        // scrapers block on reporting back to it.
+        let mut frontier = VecDeque::new();
+        // URLs handed to the scrapers whose results have not come back yet. Every URL
+        // yields exactly one result, so the crawl is done once both are empty.
+        let mut in_flight = 0usize;
 
-                for url in new_urls {
-                    if !visited_urls.contains(&url) {
-                        visited_urls.insert(url.clone());
-                        log::debug!("queueing: {}", url);
-                        let _ = urls_to_visit_tx.send(url).await;
-                    }
-                }
-            }
-
-            if new_urls_tx.capacity() == crawling_queue_capacity
-                && urls_to_visit_tx.capacity() == crawling_queue_capacity
-                && self.active_spiders.load(Ordering::SeqCst) == 0
-            {
+        for url in spider.start_urls() {
+            if max_pages.is_some_and(|max| visited_urls.len() >= max) {
                 break;
             }
+            if visited_urls.insert(url.clone()) {
+                progress.url_queued(&url, 0).await;
+                frontier.push_back((url, 0));
+            }
+        }
 
-            sleep(Duration::from_millis(5)).await;
+        while in_flight > 0 || !frontier.is_empty() {
+            tokio::select! {
+                biased;
+                _ = stop.cancelled() => {
+                    log::info!("crawl stopped, draining queued urls");
+                    break;
+                }
+                result = new_urls_rx.recv() => {
+                    let Some((visited_url, depth, new_urls)) = result else {
+                        log::error!("scrapers stopped with {} urls in flight", in_flight);
+                        break;
+                    };
+                    in_flight -= 1;
+                    log::debug!("visited: {} ({} links)", visited_url, new_urls.len());
+
+                    let depth = depth + 1;
+                    if max_depth.is_some_and(|max| depth > max) {
+                        if !new_urls.is_empty() {
+                            log::debug!("not queueing {} urls beyond max depth", new_urls.len());
+                        }
+                        continue;
+                    }
+
+                    for url in new_urls {
+                        if max_pages.is_some_and(|max| visited_urls.len() >= max) {
+                            log::debug!("page budget reached, not queueing: {}", url);
+                            break;
+                        }
+
+                        if visited_urls.insert(url.clone()) {
+                            log::debug!("queueing: {} (depth {})", url, depth);
+                            progress.url_queued(&url, depth).await;
+                            frontier.push_back((url, depth));
+                        }
+                    }
+                }
+                permit = urls_to_visit_tx.reserve(), if !frontier.is_empty() => {
+                    let Ok(permit) = permit else {
+                        log::error!("scrapers stopped with {} urls queued", frontier.len());
+                        break;
+                    };
+                    if let Some(next) = frontier.pop_front() {
+                        permit.send(next);
+                        in_flight += 1;
+                    }
+                }
+            }
         }
 
         drop(urls_to_visit_tx);
+        drop(new_urls_rx);
 
-        self.barrier.wait().await;
+        context.barrier.wait().await;
     }
 
     fn launch_processors<T, E>(
         &self,
         spider: Arc<dyn Spider<Item = T, Error = E>>,
         items: mpsc::Receiver<T>,
+        context: Arc<CrawlContext>,
     ) where
         T: Serialize + Send + 'static,
         E: Send + 'static,
     {
         let concurrency = self.processing_concurrency;
-        let barrier = self.barrier.clone();
+        let processing_slots = self.processing_slots.clone();
         tokio::spawn(async move {
             ReceiverStream::new(items)
                 .for_each_concurrent(concurrency, |item| async {
-                    let _ = spider.process(item).await;
+                    tokio::select! {
+                        biased;
+                        _ = context.cancel.cancelled() => {}
+                        _ = async {
+                            let _slot = processing_slots
+                                .acquire()
+                                .await
+                                .expect("processing semaphore is never closed");
+                            spider.process(item).await
+                        } => {}
+                    }
                 })
                 .await;
 
-            barrier.wait().await;
+            context.barrier.wait().await;
         });
     }
 
     fn launch_scrapers<T, E>(
         &self,
         spider: Arc<dyn Spider<Item = T, Error = E>>,
-        urls_to_visit: mpsc::Receiver<String>,
-        new_urls_tx: mpsc::Sender<(String, Vec<String>)>,
+        urls_to_visit: mpsc::Receiver<(String, usize)>,
+        new_urls_tx: mpsc::Sender<(String, usize, Vec<String>)>,
         items_tx: mpsc::Sender<T>,
-        _params: ScrapeParams,
+        context: Arc<CrawlContext>,
     ) where
         T: Serialize + Send + 'static,
         E: Display + Send + 'static,
     {
-        let concurrency = self.crawling_concurrency;
-        let barrier = self.barrier.clone();
-        let delay = self.delay;
-        let active_spiders = self.active_spiders.clone();
-
         tokio::spawn(async move {
+            let spider = &spider;
+            let scheduler = &context.scheduler;
+            let items_sender = &items_tx;
+            let new_urls_tx = &new_urls_tx;
+            let progress = &context.progress;
+            let stop = &context.stop;
+
+            // Concurrency is bounded by the scheduler rather than the stream, so a URL
+            // waiting on a busy host does not hold back URLs of other hosts.
             tokio_stream::wrappers::ReceiverStream::new(urls_to_visit)
-                .for_each_concurrent(concurrency, |queued_url| {
-                    let queued_url = queued_url.clone();
-                    async {
-                        active_spiders.fetch_add(1, Ordering::SeqCst);
-                        let mut urls = Vec::new();
-                        let res = spider.scrape(queued_url.clone()).await.map_err(|err| {
-                            log::error!("{}", err);
-                            err
-                        });
+                .for_each_concurrent(None, |(queued_url, depth)| async move {
+                    let mut urls = Vec::new();
+
+                    let res = tokio::select! {
+                        biased;
+                        _ = stop.cancelled() => None,
+                        res = async {
+                            let crawl_delay = spider.crawl_delay(&queued_url).await;
+                            let _permit = scheduler.acquire(&queued_url, crawl_delay).await;
+                            spider.scrape(queued_url.clone()).await
+                        } => Some(res),
+                    };
 
-                        if let Ok((items, new_urls)) = res {
+                    match res {
+                        None => {}
+                        Some(Ok((items, new_urls))) => {
                             for item in items {
-                                let _ = items_tx.send(item).await;
+                                let _ = items_sender.send(item).await;
                             }
                             urls = new_urls;
                         }
-
-                        let _ = new_urls_tx.send((queued_url, urls)).await;
-                        sleep(delay).await;
-                        active_spiders.fetch_sub(1, Ordering::SeqCst);
+                        Some(Err(err)) => {
+                            let err = err.to_string();
+                            log::error!("{}", err);
+                            progress.url_failed(&queued_url, &err).await;
+                        }
                     }
+
+                    let _ = new_urls_tx.send((queued_url, depth, urls)).await;
                 })
                 .await;
 
             drop(items_tx);
-            barrier.wait().await;
+            context.barrier.wait().await;
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    };
+
+    use async_trait::async_trait;
+
+    use super::*;
+
+    /// A tree of `total` pages in which page `n` links to its `fanout` children,
+    /// `n * fanout + 1` to `n * fanout + fanout`.
+    struct MockSpider {
+        start_urls: Vec<String>,
+        total: usize,
+        fanout: usize,
+        scraped: Mutex<Vec<String>>,
+        processed: Mutex<Vec<String>>,
+        in_flight: Arc<Gauge>,
+        process_delay: Duration,
+    }
+
+    /// Tracks how many scrapes run at once, possibly across several spiders.
+    #[derive(Default)]
+    struct Gauge {
+        current: AtomicUsize,
+        peak: AtomicUsize,
+    }
+
+    impl MockSpider {
+        fn new(start_urls: Vec<String>, total: usize, fanout: usize) -> Arc<Self> {
+            Self::with_gauge(start_urls, total, fanout, Arc::default())
+        }
+
+        fn with_gauge(
+            start_urls: Vec<String>,
+            total: usize,
+            fanout: usize,
+            in_flight: Arc<Gauge>,
+        ) -> Arc<Self> {
+            Arc::new(Self {
+                start_urls,
+                total,
+                fanout,
+                scraped: Mutex::new(vec![]),
+                processed: Mutex::new(vec![]),
+                in_flight,
+                process_delay: Duration::ZERO,
+            })
+        }
+    }
+
+    fn page_url(n: usize) -> String {
+        format!("https://host{}.test/{}", n % 4, n)
+    }
+
+    #[async_trait]
+    impl Spider for MockSpider {
+        type Item = String;
+        type Error = String;
+
+        fn name(&self) -> String {
+            "mock".to_string()
+        }
+
+        fn start_urls(&self) -> Vec<String> {
+            self.start_urls.clone()
+        }
+
+        async fn scrape(&self, url: String) -> Result<(Vec<String>, Vec<String>), String> {
+            let current = self.in_flight.current.fetch_add(1, Ordering::SeqCst) + 1;
+            self.in_flight.peak.fetch_max(current, Ordering::SeqCst);
+            tokio::task::yield_now().await;
+            self.scraped.lock().unwrap().push(url.clone());
+            self.in_flight.current.fetch_sub(1, Ordering::SeqCst);
+
+            let n: usize = url.rsplit('/').next().unwrap().parse().unwrap();
+            let links = (1..=self.fanout)
+                .map(|i| n * self.fanout + i)
+                .filter(|child| *child < self.total)
+                .map(page_url)
+                .collect();
+
+            Ok((vec![url], links))
+        }
+
+        async fn process(&self, item: String) -> Result<(), String> {
+            tokio::time::sleep(self.process_delay).await;
+            self.processed.lock().unwrap().push(item);
+            Ok(())
+        }
+    }
+
+    async fn crawl(spider: Arc<MockSpider>) {
+        crawl_with(&Crawler::new(Duration::ZERO, 4, 16, 16), spider).await;
+    }
+
+    async fn crawl_with(crawler: &Crawler, spider: Arc<MockSpider>) {
+        let cancel = CancellationToken::new();
+        crawl_until(crawler, spider, cancel.clone(), cancel).await;
+    }
+
+    async fn crawl_until(
+        crawler: &Crawler,
+        spider: Arc<MockSpider>,
+        cancel: CancellationToken,
+        stop: CancellationToken,
+    ) {
+        let params = ScrapeParams::for_test("https://host0.test/0");
+
+        tokio::time::timeout(
+            Duration::from_secs(30),
+            crawler.crawl(
+                spider,
+                params,
+                Arc::new(ProgressReporter::silent()),
+                cancel,
+                stop,
+            ),
+        )
+        .await
+        .expect("crawl did not terminate");
+    }
+
+    fn sorted(urls: &Mutex<Vec<String>>) -> Vec<String> {
+        let mut urls = urls.lock().unwrap().clone();
+        urls.sort();
+        urls
+    }
+
+    #[tokio::test]
+    async fn terminates_without_urls() {
+        let spider = MockSpider::new(vec![], 10, 2);
+        crawl(spider.clone()).await;
+
+        assert!(spider.scraped.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn crawls_a_single_url() {
+        let spider = MockSpider::new(vec![page_url(0)], 1, 2);
+        crawl(spider.clone()).await;
+
+        assert_eq!(sorted(&spider.scraped), vec![page_url(0)]);
+        assert_eq!(sorted(&spider.processed), vec![page_url(0)]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn visits_every_url_exactly_once() {
+        // More URLs than the crawling queue holds, so the coordinator has to buffer them.
+        let total = 10_000;
+        let spider = MockSpider::new(vec![page_url(0), page_url(0)], total, 10);
+        crawl(spider.clone()).await;
+
+        let mut expected: Vec<String> = (0..total).map(page_url).collect();
+        expected.sort();
+        assert_eq!(sorted(&spider.scraped), expected);
+        assert_eq!(sorted(&spider.processed), expected);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn runs_concurrent_crawls_independently() {
+        let crawler = Crawler::new(Duration::ZERO, 4, 3, 8);
+        let gauge = Arc::new(Gauge::default());
+        let spiders: Vec<_> = (0..4)
+            .map(|_| MockSpider::with_gauge(vec![page_url(0)], 500, 5, gauge.clone()))
+            .collect();
+
+        futures_util::future::join_all(
+            spiders
+                .iter()
+                .map(|spider| crawl_with(&crawler, spider.clone())),
+        )
+        .await;
+
+        let mut expected: Vec<String> = (0..500).map(page_url).collect();
+        expected.sort();
+        for spider in &spiders {
+            assert_eq!(sorted(&spider.scraped), expected);
+            assert_eq!(sorted(&spider.processed), expected);
+        }
+        assert!(gauge.peak.load(Ordering::SeqCst) <= 3);
+    }
+
+    #[tokio::test]
+    async fn stopping_lets_items_in_processing_finish() {
+        let crawler = Crawler::new(Duration::ZERO, 4, 16, 16);
+        let slow_spider = || {
+            Arc::new(MockSpider {
+                process_delay: Duration::from_millis(200),
+                ..Arc::into_inner(MockSpider::new(vec![page_url(0)], 1_000, 2)).unwrap()
+            })
+        };
+        let after = |token: CancellationToken| {
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                token.cancel();
+            });
+        };
+
+        let spider = slow_spider();
+        let cancel = CancellationToken::new();
+        let stop = cancel.child_token();
+        after(stop.clone());
+        crawl_until(&crawler, spider.clone(), cancel, stop).await;
+        assert!(spider.processed.lock().unwrap().contains(&page_url(0)));
+        assert!(spider.scraped.lock().unwrap().len() < 1_000);
+
+        let spider = slow_spider();
+        let cancel = CancellationToken::new();
+        after(cancel.clone());
+        crawl_until(
+            &crawler,
+            spider.clone(),
+            cancel.clone(),
+            cancel.child_token(),
+        )
+        .await;
+        assert!(spider.processed.lock().unwrap().is_empty());
+    }
+}
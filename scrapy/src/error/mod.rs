@@ -13,9 +13,33 @@ pub enum AppError {
     #[error("Serialization error: {0}")]
     Serialization(#[from] serde_json::Error),
 
+    #[error("{url} answered with HTTP status {status}")]
+    HttpStatus { url: String, status: u16 },
+
+    #[error("Unknown model: {0}")]
+    UnknownModel(String),
+
+    #[error("Invalid parameters: {0}")]
+    InvalidParams(String),
+
     #[error("AI error: {0}")]
     AI(String),
 
+    #[error("Budget exceeded: {0}")]
+    BudgetExceeded(String),
+
+    #[error("Not in the page cache: {0}")]
+    CacheMiss(String),
+
+    #[error("Not recorded in the WARC file: {0}")]
+    NotRecorded(String),
+
+    #[error("Export error: {0}")]
+    Export(String),
+
+    #[error("Storage error: {0}")]
+    Storage(#[from] rusqlite::Error),
+
     #[error(transparent)]
     WebSocket(#[from] WebSocketError),
 }
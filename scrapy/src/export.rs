@@ -0,0 +1,479 @@
+//! Crawl results in the formats analysts load into other tools: the nested JSON the API
+//! has always returned, JSON Lines, and CSV or Excel tables with one row per record.
+
+use std::collections::BTreeMap;
+use std::io::Cursor;
+
+use rocket::http::{ContentType, Header, MediaType, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::Json;
+use rust_xlsxwriter::{Format, Workbook};
+use serde_json::Value;
+
+use crate::error::AppError;
+use crate::models::{ScrapeParams, ScrapingResult};
+
+/// Page columns that come before the record fields in tables.
+const PAGE_COLUMNS: [&str; 5] = [
+    "page.url",
+    "page.finalUrl",
+    "page.status",
+    "page.skipped",
+    "page.error",
+];
+
+/// Column of records that are not JSON objects.
+const VALUE_COLUMN: &str = "value";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Json,
+    Ndjson,
+    Csv,
+    Xlsx,
+}
+
+impl ExportFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "json" => Some(Self::Json),
+            "ndjson" | "jsonl" => Some(Self::Ndjson),
+            "csv" => Some(Self::Csv),
+            "xlsx" => Some(Self::Xlsx),
+            _ => None,
+        }
+    }
+
+    pub fn from_media_type(media_type: &MediaType) -> Option<Self> {
+        let top = media_type.top().as_str().to_ascii_lowercase();
+        let sub = media_type.sub().as_str().to_ascii_lowercase();
+        match (top.as_str(), sub.as_str()) {
+            ("application", "json") => Some(Self::Json),
+            ("application", "x-ndjson" | "jsonl" | "jsonlines") => Some(Self::Ndjson),
+            ("text", "csv") => Some(Self::Csv),
+            ("application", "vnd.openxmlformats-officedocument.spreadsheetml.sheet") => {
+                Some(Self::Xlsx)
+            }
+            _ => None,
+        }
+    }
+
+    fn content_type(self) -> ContentType {
+        match self {
+            Self::Json => ContentType::JSON,
+            Self::Ndjson => ContentType::new("application", "x-ndjson"),
+            Self::Csv => ContentType::CSV,
+            Self::Xlsx => ContentType::new(
+                "application",
+                "vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+            ),
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Ndjson => "ndjson",
+            Self::Csv => "csv",
+            Self::Xlsx => "xlsx",
+        }
+    }
+}
+
+/// The `format` query parameter when given, otherwise the preferred `Accept` type. Types
+/// without an export format, such as `*/*`, get JSON.
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ExportFormat {
+    type Error = AppError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        if let Some(name) = request.query_value::<&str>("format") {
+            let name = name.unwrap_or_default();
+            return match Self::from_name(name) {
+                Some(format) => Outcome::Success(format),
+                None => Outcome::Error((
+                    Status::BadRequest,
+                    AppError::InvalidParams(format!("unknown export format '{}'", name)),
+                )),
+            };
+        }
+
+        let format = request
+            .accept()
+            .and_then(|accept| Self::from_media_type(accept.preferred().media_type()));
+        Outcome::Success(format.unwrap_or(Self::Json))
+    }
+}
+
+/// Crawl results rendered in an `ExportFormat`.
+pub struct Export {
+    format: ExportFormat,
+    fields: Vec<String>,
+    results: Vec<ScrapingResult>,
+}
+
+impl Export {
+    pub fn new(format: ExportFormat, params: &ScrapeParams, results: Vec<ScrapingResult>) -> Self {
+        Self {
+            format,
+            fields: record_fields(params),
+            results,
+        }
+    }
+
+    /// One JSON `ScrapingResult` per line.
+    pub fn to_ndjson(&self) -> Result<Vec<u8>, AppError> {
+        let mut body = Vec::new();
+        for result in &self.results {
+            serde_json::to_writer(&mut body, result)?;
+            body.push(b'\n');
+        }
+        Ok(body)
+    }
+
+    pub fn to_csv(&self) -> Result<Vec<u8>, AppError> {
+        let (columns, rows) = self.table();
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.write_record(&columns).map_err(export_error)?;
+        for row in &rows {
+            writer
+                .write_record(columns.iter().map(|column| match row.get(column) {
+                    None | Some(Value::Null) => String::new(),
+                    Some(Value::String(text)) => text.clone(),
+                    Some(value) => value.to_string(),
+                }))
+                .map_err(export_error)?;
+        }
+        writer
+            .into_inner()
+            .map_err(|e| AppError::Export(e.to_string()))
+    }
+
+    pub fn to_xlsx(&self) -> Result<Vec<u8>, AppError> {
+        let (columns, rows) = self.table();
+        let mut workbook = Workbook::new();
+        let sheet = workbook.add_worksheet();
+        sheet.set_name("Results").map_err(export_error)?;
+
+        let bold = Format::new().set_bold();
+        for (col, column) in columns.iter().enumerate() {
+            sheet
+                .write_string_with_format(0, col as u16, column, &bold)
+                .map_err(export_error)?;
+        }
+        for (row, values) in rows.iter().enumerate() {
+            let row = row as u32 + 1;
+            for (col, column) in columns.iter().enumerate() {
+                let col = col as u16;
+                match values.get(column) {
+                    None | Some(Value::Null) => continue,
+                    Some(Value::Bool(value)) => sheet.write_boolean(row, col, *value),
+                    Some(Value::Number(value)) => match value.as_f64() {
+                        Some(number) => sheet.write_number(row, col, number),
+                        None => sheet.write_string(row, col, value.to_string()),
+                    },
+                    Some(Value::String(text)) => sheet.write_string(row, col, text),
+                    Some(value) => sheet.write_string(row, col, value.to_string()),
+                }
+                .map_err(export_error)?;
+            }
+        }
+
+        workbook.save_to_buffer().map_err(export_error)
+    }
+
+    /// Columns and rows shared by CSV and Excel. Every record is a row that repeats its
+    /// page's columns; pages without records get one row of page columns. Record columns
+    /// follow the schema, selector fields or tags, then fields only found in the records, in
+    /// the order they first appear.
+    fn table(&self) -> (Vec<String>, Vec<BTreeMap<String, Value>>) {
+        let mut columns: Vec<String> = PAGE_COLUMNS.iter().map(|c| c.to_string()).collect();
+        columns.extend(self.fields.iter().cloned());
+        let mut rows = Vec::new();
+
+        for result in &self.results {
+            let page = BTreeMap::from([
+                (PAGE_COLUMNS[0].to_string(), Value::from(result.url.clone())),
+                (
+                    PAGE_COLUMNS[1].to_string(),
+                    Value::from(result.final_url.clone()),
+                ),
+                (PAGE_COLUMNS[2].to_string(), Value::from(result.status)),
+                (
+                    PAGE_COLUMNS[3].to_string(),
+                    Value::from(result.skipped.clone()),
+                ),
+                (
+                    PAGE_COLUMNS[4].to_string(),
+                    Value::from(result.error.clone()),
+                ),
+            ]);
+            if result.all_data.is_empty() {
+                rows.push(page);
+                continue;
+            }
+
+            for record in &result.all_data {
+                let mut row = page.clone();
+                match record {
+                    Value::Object(_) => flatten(record, "", &mut row),
+                    _ => {
+                        row.insert(VALUE_COLUMN.to_string(), record.clone());
+                    }
+                }
+                for column in row.keys() {
+                    if !columns.contains(column) {
+                        columns.push(column.clone());
+                    }
+                }
+                rows.push(row);
+            }
+        }
+
+        (columns, rows)
+    }
+
+    fn body(&self) -> Result<Vec<u8>, AppError> {
+        match self.format {
+            ExportFormat::Json => Ok(serde_json::to_vec(&self.results)?),
+            ExportFormat::Ndjson => self.to_ndjson(),
+            ExportFormat::Csv => self.to_csv(),
+            ExportFormat::Xlsx => self.to_xlsx(),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for Export {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        if self.format == ExportFormat::Json {
+            return Json(self.results).respond_to(request);
+        }
+
+        let body = self.body().map_err(|e| {
+            log::error!("Failed to export crawl results: {}", e);
+            Status::InternalServerError
+        })?;
+        Response::build()
+            .header(self.format.content_type())
+            .header(Header::new(
+                "Content-Disposition",
+                format!(
+                    "attachment; filename=\"results.{}\"",
+                    self.format.extension()
+                ),
+            ))
+            .sized_body(body.len(), Cursor::new(body))
+            .ok()
+    }
+}
+
+/// Record columns declared by `params`: the schema's properties, with nested object
+/// properties as `parent.child`, or else the selector fields, or else the tags.
+pub fn record_fields(params: &ScrapeParams) -> Vec<String> {
+    if let Some(schema) = &params.schema {
+        let mut fields = vec![];
+        schema_fields(schema, "", &mut fields);
+        fields
+    } else if let Some(extraction) = &params.extraction {
+        extraction.fields.keys().cloned().collect()
+    } else {
+        params.tags.clone()
+    }
+}
+
+fn schema_fields(schema: &Value, prefix: &str, fields: &mut Vec<String>) {
+    let Some(properties) = schema.get("properties").and_then(Value::as_object) else {
+        return;
+    };
+    for (name, property) in properties {
+        let path = join(prefix, name);
+        if property.get("properties").is_some_and(Value::is_object) {
+            schema_fields(property, &path, fields);
+        } else {
+            fields.push(path);
+        }
+    }
+}
+
+/// Nested objects become `parent.child` columns; arrays are kept as JSON text. Nulls are
+/// left out, so that a missing nested object does not add a column of its own.
+fn flatten(value: &Value, prefix: &str, row: &mut BTreeMap<String, Value>) {
+    match value {
+        Value::Null => {}
+        Value::Object(object) => {
+            for (name, value) in object {
+                flatten(value, &join(prefix, name), row);
+            }
+        }
+        Value::Array(_) => {
+            row.insert(prefix.to_string(), Value::String(value.to_string()));
+        }
+        _ => {
+            row.insert(prefix.to_string(), value.clone());
+        }
+    }
+}
+
+fn join(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", prefix, name)
+    }
+}
+
+fn export_error(e: impl std::fmt::Display) -> AppError {
+    AppError::Export(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::models::{AiScrapingResult, CrawlReport, PageMetadata, UsageMetadata};
+
+    fn results() -> Vec<ScrapingResult> {
+        vec![
+            ScrapingResult {
+                url: "https://example.com/a".to_string(),
+                status: Some(200),
+                all_data: vec![
+                    json!({
+                        "name": "Lamp",
+                        "price": 12.5,
+                        "seller": { "city": "Cairo", "name": "Nour" },
+                        "tags": ["home", "light"],
+                        "extra": true,
+                    }),
+                    json!({ "name": "Desk, oak", "seller": null, "price": null }),
+                ],
+                ..Default::default()
+            },
+            ScrapingResult {
+                url: "https://example.com/b".to_string(),
+                skipped: Some("disallowed by robots.txt".to_string()),
+                ..Default::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn flattens_records_into_csv_rows() {
+        let mut params = ScrapeParams::for_test("https://example.com/a");
+        params.schema = Some(json!({
+            "type": "object",
+            "properties": {
+                "price": { "type": "number" },
+                "name": { "type": "string" },
+                "seller": {
+                    "type": "object",
+                    "properties": { "name": { "type": "string" }, "city": { "type": "string" } },
+                },
+                "tags": { "type": "array", "items": { "type": "string" } },
+            },
+        }));
+
+        let csv = Export::new(ExportFormat::Csv, &params, results())
+            .to_csv()
+            .unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "page.url,page.finalUrl,page.status,page.skipped,page.error,name,price,seller.city,seller.name,tags,extra",
+                r#"https://example.com/a,,200,,,Lamp,12.5,Cairo,Nour,"[""home"",""light""]",true"#,
+                r#"https://example.com/a,,200,,,"Desk, oak",,,,,"#,
+                "https://example.com/b,,,disallowed by robots.txt,,,,,,,",
+            ]
+        );
+
+        params.schema = None;
+        params.tags = vec!["price".to_string(), "name".to_string()];
+        let export = Export::new(ExportFormat::Csv, &params, results());
+        assert_eq!(
+            export.table().0[PAGE_COLUMNS.len()..],
+            [
+                "price",
+                "name",
+                "extra",
+                "seller.city",
+                "seller.name",
+                "tags"
+            ]
+        );
+    }
+
+    #[test]
+    fn exports_single_object_answers_as_one_record() {
+        let page = PageMetadata {
+            url: "https://example.com/a".to_string(),
+            attempts: 1,
+            error: None,
+            final_url: None,
+            status: Some(200),
+            redirect_chain: Vec::new(),
+            cache: None,
+            usage: UsageMetadata::default(),
+        };
+        let report = CrawlReport {
+            results: vec![AiScrapingResult {
+                url: page.url.clone(),
+                model: "gemini-1.5-flash".to_string(),
+                start_time: chrono::Utc::now(),
+                end_time: None,
+                data: json!({ "name": "Lamp", "price": 12.5 }),
+                usage_metadata: UsageMetadata::default(),
+                validation_errors: Vec::new(),
+                content_size: None,
+            }],
+            pagination: None,
+            skipped: Vec::new(),
+            pages: vec![page],
+            usage: UsageMetadata::default(),
+            budget_limited: false,
+        };
+
+        let params = ScrapeParams::for_test("https://example.com/a");
+        let csv = Export::new(ExportFormat::Csv, &params, report.into_scraping_results())
+            .to_csv()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap().lines().collect::<Vec<_>>(),
+            [
+                "page.url,page.finalUrl,page.status,page.skipped,page.error,name,price",
+                "https://example.com/a,,200,,,Lamp,12.5",
+            ]
+        );
+    }
+
+    #[test]
+    fn writes_json_lines_and_workbooks() {
+        let params = ScrapeParams::for_test("https://example.com/a");
+
+        let ndjson = Export::new(ExportFormat::Ndjson, &params, results())
+            .to_ndjson()
+            .unwrap();
+        let lines: Vec<Value> = String::from_utf8(ndjson)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["allData"][0]["seller"]["city"], "Cairo");
+
+        let xlsx = Export::new(ExportFormat::Xlsx, &params, results())
+            .to_xlsx()
+            .unwrap();
+        assert!(xlsx.starts_with(b"PK"));
+
+        assert_eq!(ExportFormat::from_name("JSONL"), Some(ExportFormat::Ndjson));
+        assert_eq!(
+            ExportFormat::from_media_type(&MediaType::CSV),
+            Some(ExportFormat::Csv)
+        );
+        assert_eq!(ExportFormat::from_media_type(&MediaType::Any), None);
+    }
+}
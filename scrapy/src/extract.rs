@@ -0,0 +1,531 @@
+use regex::Regex;
+use scraper::{ElementRef, Html, Selector};
+use serde_json::{Map, Number, Value};
+use sxd_document::Package;
+
+use crate::{
+    error::AppError,
+    models::{ExtractKind, FieldSelector, SelectorExtraction, Transform},
+    xpath::{self, Match, XPathDocument},
+};
+
+/// Builds records from a document with the CSS selectors and XPath expressions of a
+/// `SelectorExtraction`.
+pub struct SelectorExtractor {
+    item: Option<Query>,
+    fields: Vec<(String, Field)>,
+    /// Whether documents need mirroring for XPath before extraction.
+    uses_xpath: bool,
+}
+
+enum Query {
+    Css(Selector),
+    /// Compiled per document, since `sxd-xpath` expressions are not `Send`.
+    XPath(String),
+}
+
+struct Field {
+    query: Option<Query>,
+    extract: ExtractKind,
+    attribute: String,
+    multiple: bool,
+    transforms: Vec<CompiledTransform>,
+}
+
+enum CompiledTransform {
+    Trim,
+    Regex(Regex, Option<usize>),
+    Number(Option<char>),
+}
+
+impl SelectorExtractor {
+    pub fn new(extraction: &SelectorExtraction) -> Result<Self, AppError> {
+        let item = Query::new(
+            "itemSelector",
+            extraction.item_selector.as_deref(),
+            extraction.item_xpath.as_deref(),
+        )?;
+
+        let fields: Vec<(String, Field)> = extraction
+            .fields
+            .iter()
+            .map(|(name, field)| Ok((name.clone(), Field::new(name, field)?)))
+            .collect::<Result<_, AppError>>()?;
+
+        let uses_xpath = item
+            .iter()
+            .chain(fields.iter().filter_map(|(_, field)| field.query.as_ref()))
+            .any(|query| matches!(query, Query::XPath(_)));
+
+        Ok(Self {
+            item,
+            fields,
+            uses_xpath,
+        })
+    }
+
+    /// One record per item element, or a single record for the whole document when no
+    /// item selector is set.
+    pub fn extract(&self, document: &Html) -> Vec<Value> {
+        let package;
+        let xpath = if self.uses_xpath {
+            package = Package::new();
+            Some(XPathDocument::new(&package, document))
+        } else {
+            None
+        };
+        let xpath = xpath.as_ref();
+
+        match &self.item {
+            Some(Query::Css(item)) => document
+                .select(item)
+                .map(|element| self.record(element, xpath))
+                .collect(),
+            Some(item) => item
+                .select(document.root_element(), xpath)
+                .filter_map(|found| match found {
+                    Match::Element(element) => Some(self.record(element, xpath)),
+                    _ => None,
+                })
+                .collect(),
+            None => vec![self.record(document.root_element(), xpath)],
+        }
+    }
+
+    fn record<'a>(&self, element: ElementRef<'a>, xpath: Option<&XPathDocument<'a>>) -> Value {
+        let record: Map<String, Value> = self
+            .fields
+            .iter()
+            .map(|(name, field)| (name.clone(), field.value(element, xpath)))
+            .collect();
+        Value::Object(record)
+    }
+}
+
+impl Query {
+    /// At most one of `selector` and `xpath` may be set.
+    fn new(
+        name: &str,
+        selector: Option<&str>,
+        xpath: Option<&str>,
+    ) -> Result<Option<Self>, AppError> {
+        match (selector, xpath) {
+            (None, None) => Ok(None),
+            (Some(selector), None) => parse_selector(name, selector).map(|s| Some(Self::Css(s))),
+            (None, Some(expression)) => xpath::validate(expression)
+                .map(|()| Some(Self::XPath(expression.to_string())))
+                .map_err(|e| {
+                    AppError::InvalidParams(format!(
+                        "field '{}': XPath '{}': {}",
+                        name, expression, e
+                    ))
+                }),
+            (Some(_), Some(_)) => Err(AppError::InvalidParams(format!(
+                "field '{}': set either a CSS selector or an XPath, not both",
+                name
+            ))),
+        }
+    }
+
+    fn select<'a>(
+        &'a self,
+        scope: ElementRef<'a>,
+        xpath: Option<&XPathDocument<'a>>,
+    ) -> Box<dyn Iterator<Item = Match<'a>> + 'a> {
+        match (self, xpath) {
+            (Self::Css(selector), _) => Box::new(scope.select(selector).map(Match::Element)),
+            (Self::XPath(expression), Some(xpath)) => {
+                Box::new(xpath.select(scope, expression).into_iter())
+            }
+            (Self::XPath(_), None) => Box::new(std::iter::empty()),
+        }
+    }
+}
+
+impl Field {
+    fn new(name: &str, field: &FieldSelector) -> Result<Self, AppError> {
+        let query = Query::new(name, field.selector.as_deref(), field.xpath.as_deref())?;
+
+        let attribute = match (field.extract, &field.attribute) {
+            (ExtractKind::Attribute, Some(attribute)) => attribute.clone(),
+            (ExtractKind::Attribute, None) => {
+                return Err(AppError::InvalidParams(format!(
+                    "field '{}': attribute extraction needs an attribute name",
+                    name
+                )))
+            }
+            _ => String::new(),
+        };
+
+        let transforms = field
+            .transforms
+            .iter()
+            .map(|transform| {
+                Ok(match transform {
+                    Transform::Trim => CompiledTransform::Trim,
+                    Transform::Number { decimal_separator } => match decimal_separator {
+                        None | Some('.' | ',') => CompiledTransform::Number(*decimal_separator),
+                        Some(separator) => {
+                            return Err(AppError::InvalidParams(format!(
+                                "field '{}': decimal separator '{}' is not '.' or ','",
+                                name, separator
+                            )))
+                        }
+                    },
+                    Transform::Regex { pattern, group } => CompiledTransform::Regex(
+                        Regex::new(pattern).map_err(|e| {
+                            AppError::InvalidParams(format!(
+                                "field '{}': pattern '{}': {}",
+                                name, pattern, e
+                            ))
+                        })?,
+                        *group,
+                    ),
+                })
+            })
+            .collect::<Result<_, AppError>>()?;
+
+        Ok(Self {
+            query,
+            extract: field.extract,
+            attribute,
+            multiple: field.multiple,
+            transforms,
+        })
+    }
+
+    fn value<'a>(&'a self, scope: ElementRef<'a>, xpath: Option<&XPathDocument<'a>>) -> Value {
+        let mut matches = match &self.query {
+            Some(query) => query.select(scope, xpath),
+            None => Box::new(std::iter::once(Match::Element(scope))),
+        };
+
+        if self.multiple {
+            Value::Array(matches.map(|found| self.match_value(found)).collect())
+        } else {
+            matches
+                .next()
+                .map(|found| self.match_value(found))
+                .unwrap_or(Value::Null)
+        }
+    }
+
+    fn match_value(&self, found: Match) -> Value {
+        let raw = match found {
+            Match::Element(element) => match self.extract {
+                ExtractKind::Text => Some(element.text().collect::<String>()),
+                ExtractKind::InnerHtml => Some(element.inner_html()),
+                ExtractKind::Html => Some(element.html()),
+                ExtractKind::Attribute => element.value().attr(&self.attribute).map(String::from),
+            }
+            .map_or(Value::Null, Value::String),
+            Match::Text(text) => Value::String(text),
+            Match::Number(number) => number_value(number),
+            Match::Boolean(boolean) => Value::Bool(boolean),
+        };
+
+        self.transforms
+            .iter()
+            .fold(raw, |value, transform| transform.apply(value))
+    }
+}
+
+impl CompiledTransform {
+    fn apply(&self, value: Value) -> Value {
+        let Value::String(text) = value else {
+            return value;
+        };
+
+        match self {
+            Self::Trim => Value::String(text.split_whitespace().collect::<Vec<_>>().join(" ")),
+            Self::Regex(regex, group) => {
+                let group = group.unwrap_or(if regex.captures_len() > 1 { 1 } else { 0 });
+                regex
+                    .captures(&text)
+                    .and_then(|captures| captures.get(group))
+                    .map_or(Value::Null, |m| Value::String(m.as_str().to_string()))
+            }
+            Self::Number(decimal) => parse_number(&text, *decimal),
+        }
+    }
+}
+
+/// Parses the one number in `text`, so that `$1,299.50`, `1.299,50 €` and `1 299,50` all
+/// give `1299.5`. With both `.` and `,` the last one is the decimal separator, and a
+/// repeated one groups thousands. A lone separator before three digits, as in `1,299`,
+/// could be either and gives `null` unless `decimal` says which it is. A `-` counts only
+/// when it is not part of a word, so `SKU-123` gives `123`.
+fn parse_number(text: &str, decimal: Option<char>) -> Value {
+    let mut tokens = number_tokens(text);
+    let (Some((negative, token)), None) = (tokens.next(), tokens.next()) else {
+        return Value::Null;
+    };
+    let Some(digits) = normalize_number(&token, decimal) else {
+        return Value::Null;
+    };
+    let digits = if negative {
+        format!("-{}", digits)
+    } else {
+        digits
+    };
+
+    if let Ok(integer) = digits.parse::<i64>() {
+        return Value::Number(integer.into());
+    }
+    digits.parse().map_or(Value::Null, number_value)
+}
+
+/// Whole numbers become JSON integers, so that `3.0` is written as `3`.
+fn number_value(number: f64) -> Value {
+    if number.fract() == 0.0 && number.abs() < i64::MAX as f64 {
+        Value::Number((number as i64).into())
+    } else {
+        Number::from_f64(number).map_or(Value::Null, Value::Number)
+    }
+}
+
+/// Runs of digits joined by single separators, with whether a minus sign leads them.
+fn number_tokens(text: &str) -> impl Iterator<Item = (bool, String)> + '_ {
+    let chars: Vec<char> = text.chars().collect();
+    let mut i = 0;
+
+    std::iter::from_fn(move || {
+        while i < chars.len() && !chars[i].is_ascii_digit() {
+            i += 1;
+        }
+        if i == chars.len() {
+            return None;
+        }
+
+        let negative = i > 0
+            && matches!(chars[i - 1], '-' | '\u{2212}')
+            && (i < 2 || !chars[i - 2].is_alphanumeric());
+
+        let start = i;
+        while i < chars.len() {
+            if chars[i].is_ascii_digit() {
+                i += 1;
+            } else if is_number_separator(chars[i])
+                && chars.get(i + 1).is_some_and(char::is_ascii_digit)
+            {
+                i += 2;
+            } else {
+                break;
+            }
+        }
+        Some((negative, chars[start..i].iter().collect()))
+    })
+}
+
+fn is_number_separator(c: char) -> bool {
+    matches!(c, '.' | ',' | '\'' | ' ' | '\u{a0}' | '\u{202f}')
+}
+
+/// Rewrites a token from `number_tokens` as `1299.5`, or `None` when it is ambiguous or
+/// its thousands groups are malformed.
+fn normalize_number(token: &str, decimal: Option<char>) -> Option<String> {
+    let separators: Vec<char> = token.chars().filter(|c| !c.is_ascii_digit()).collect();
+    let marks = |c: char| separators.iter().filter(|&&s| s == c).count();
+
+    let decimal = match decimal {
+        Some(decimal) => match marks(decimal) {
+            0 => None,
+            1 => Some(decimal),
+            _ => return None,
+        },
+        None => match (marks('.'), marks(',')) {
+            (0, 0) => None,
+            (_, 0) | (0, _) => {
+                let mark = if marks('.') > 0 { '.' } else { ',' };
+                let (integer, fraction) = token.rsplit_once(mark)?;
+                let grouped = separators.iter().any(|&s| s != mark);
+                if marks(mark) > 1 {
+                    None
+                } else if grouped || fraction.len() != 3 || integer == "0" {
+                    Some(mark)
+                } else {
+                    return None;
+                }
+            }
+            _ => separators
+                .iter()
+                .rev()
+                .copied()
+                .find(|c| matches!(c, '.' | ',')),
+        },
+    };
+
+    let (integer, fraction) = match decimal {
+        Some(decimal) => token.rsplit_once(decimal)?,
+        None => (token, ""),
+    };
+    if !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let mut groups = integer.split(|c: char| !c.is_ascii_digit());
+    let first = groups.next()?;
+    let mut grouping = integer.chars().filter(|c| !c.is_ascii_digit());
+    let consistent = grouping
+        .next()
+        .is_none_or(|g| grouping.all(|c| c == g) && first.len() <= 3);
+    if !consistent || !groups.clone().all(|group| group.len() == 3) {
+        return None;
+    }
+
+    let integer: String = std::iter::once(first).chain(groups).collect();
+    Some(if fraction.is_empty() {
+        integer
+    } else {
+        format!("{}.{}", integer, fraction)
+    })
+}
+
+/// XPath given as a CSS selector is pointed at the XPath setting instead of failing to parse.
+fn parse_selector(name: &str, selector: &str) -> Result<Selector, AppError> {
+    if selector.starts_with('/') || selector.starts_with("./") || selector.starts_with('(') {
+        return Err(AppError::InvalidParams(format!(
+            "field '{}': '{}' looks like XPath; set it as an XPath instead",
+            name, selector
+        )));
+    }
+
+    Selector::parse(selector).map_err(|e| {
+        AppError::InvalidParams(format!("field '{}': selector '{}': {}", name, selector, e))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn extractor(extraction: Value) -> Result<SelectorExtractor, AppError> {
+        SelectorExtractor::new(&serde_json::from_value(extraction).unwrap())
+    }
+
+    #[test]
+    fn extracts_records_from_items() {
+        let document = Html::parse_document(
+            r#"<ul>
+                <li class="product"><a href="/lamp"> Desk
+                    lamp </a><span class="price">$1,299.50</span>
+                    <span class="tag">home</span><span class="tag">light</span>
+                    <span class="sku">SKU: LMP-42</span></li>
+                <li class="product"><a href="/chair">Chair</a><span class="price">€ 80</span></li>
+            </ul>"#,
+        );
+
+        let extractor = extractor(json!({
+            "itemSelector": "li.product",
+            "fields": {
+                "name": { "selector": "a", "transforms": [{ "type": "trim" }] },
+                "link": { "selector": "a", "extract": "attribute", "attribute": "href" },
+                "price": { "selector": ".price", "transforms": [{ "type": "number" }] },
+                "tags": { "selector": ".tag", "multiple": true },
+                "sku": {
+                    "selector": ".sku",
+                    "transforms": [{ "type": "regex", "pattern": "SKU: (\\S+)" }],
+                },
+            },
+        }))
+        .unwrap();
+
+        assert_eq!(
+            extractor.extract(&document),
+            vec![
+                json!({
+                    "name": "Desk lamp",
+                    "link": "/lamp",
+                    "price": 1299.5,
+                    "tags": ["home", "light"],
+                    "sku": "LMP-42",
+                }),
+                json!({
+                    "name": "Chair",
+                    "link": "/chair",
+                    "price": 80,
+                    "tags": [],
+                    "sku": null,
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn extracts_records_with_xpath() {
+        let document = Html::parse_document(
+            r#"<table>
+                <tr><th>Name</th><th>Stock</th></tr>
+                <tr><td><a href="/lamp">Lamp</a></td><td>3</td></tr>
+                <tr><td><a href="/chair">Chair</a></td><td>0</td></tr>
+            </table>"#,
+        );
+
+        let extractor = extractor(json!({
+            "itemXPath": "//tr[td]",
+            "fields": {
+                "name": { "xpath": "td[1]/a" },
+                "link": { "xpath": "td[1]/a/@href" },
+                "inStock": { "xpath": "number(td[2]) > 0" },
+                "cells": { "xpath": "count(td)" },
+                "row": { "selector": "a", "extract": "attribute", "attribute": "href" },
+            },
+        }))
+        .unwrap();
+
+        assert_eq!(
+            extractor.extract(&document),
+            vec![
+                json!({ "name": "Lamp", "link": "/lamp", "inStock": true, "cells": 2, "row": "/lamp" }),
+                json!({ "name": "Chair", "link": "/chair", "inStock": false, "cells": 2, "row": "/chair" }),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_fields() {
+        let field = |field: Value| extractor(json!({ "fields": { "title": field } }));
+
+        assert!(field(json!({ "selector": "h1" })).is_ok());
+        assert!(matches!(
+            field(json!({ "selector": "//h1" })),
+            Err(AppError::InvalidParams(message)) if message.contains("XPath")
+        ));
+        assert!(
+            field(json!({ "transforms": [{ "type": "number", "decimalSeparator": " " }] }))
+                .is_err()
+        );
+        assert!(field(json!({ "selector": "h1[" })).is_err());
+        assert!(field(json!({ "xpath": "//h1" })).is_ok());
+        assert!(field(json!({ "xpath": "//h1[" })).is_err());
+        assert!(field(json!({ "selector": "h1", "xpath": "//h1" })).is_err());
+        assert!(field(json!({ "selector": "a", "extract": "attribute" })).is_err());
+        assert!(field(json!({ "transforms": [{ "type": "regex", "pattern": "(" }] })).is_err());
+    }
+
+    #[test]
+    fn parses_numbers() {
+        for (text, number) in [
+            ("$1,299.50", json!(1299.5)),
+            ("1.299,50 €", json!(1299.5)),
+            ("1 299,50", json!(1299.5)),
+            ("1'299.50 CHF", json!(1299.5)),
+            ("1,299,000", json!(1299000)),
+            ("0.125", json!(0.125)),
+            ("3,5", json!(3.5)),
+            ("-12", json!(-12)),
+            ("SKU-123", json!(123)),
+            ("1,299", Value::Null),
+            ("1,29,9", Value::Null),
+            ("12 or 13", Value::Null),
+            ("n/a", Value::Null),
+        ] {
+            assert_eq!(parse_number(text, None), number, "{}", text);
+        }
+
+        assert_eq!(parse_number("1,299", Some('.')), json!(1299));
+        assert_eq!(parse_number("1,299", Some(',')), json!(1.299));
+        assert_eq!(parse_number("1.2.3", Some('.')), Value::Null);
+    }
+}
@@ -0,0 +1,206 @@
+//! On-disk cache of page responses, so that crawling a site again does not download
+//! every page again.
+
+use std::{collections::BTreeMap, path::PathBuf, time::Duration};
+
+use chrono::{DateTime, Utc};
+use encoding_rs::{Encoding, UTF_8};
+use reqwest::header::{HeaderMap, CONTENT_TYPE};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use crate::error::AppError;
+use crate::utils::{cache_dir, write_atomically};
+
+/// How a crawl uses the page cache.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CacheOptions {
+    pub mode: CacheMode,
+    /// Age in seconds up to which a cached page is used without asking the site whether
+    /// it changed.
+    pub ttl_secs: u64,
+}
+
+impl Default for CacheOptions {
+    fn default() -> Self {
+        Self {
+            mode: CacheMode::Off,
+            ttl_secs: 3600,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum CacheMode {
+    /// Pages are always downloaded and never stored.
+    #[default]
+    Off,
+    /// Fresh pages are served from the cache, stale ones are revalidated with the site.
+    On,
+    /// Pages are only served from the cache, whatever their age; pages missing from it
+    /// fail without a request.
+    Offline,
+}
+
+/// A response as stored in the cache.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CachedResponse {
+    pub url: String,
+    pub status: u16,
+    /// Header names are lowercase; repeated headers keep their last value.
+    pub headers: BTreeMap<String, String>,
+    pub fetched_at: DateTime<Utc>,
+    pub body: String,
+}
+
+impl CachedResponse {
+    pub fn new(url: &Url, status: u16, headers: &HeaderMap, body: String) -> Self {
+        let headers = headers
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+
+        Self {
+            url: url.to_string(),
+            status,
+            headers,
+            fetched_at: Utc::now(),
+            body,
+        }
+    }
+
+    /// Decodes `body` with the charset of its `Content-Type`, like `Response::text`.
+    pub fn from_bytes(url: &Url, status: u16, headers: &HeaderMap, body: &[u8]) -> Self {
+        let content_type = headers.get(CONTENT_TYPE).and_then(|v| v.to_str().ok());
+        Self::new(url, status, headers, decode_body(content_type, body))
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(String::as_str)
+    }
+
+    pub fn is_fresh(&self, ttl: Duration) -> bool {
+        Utc::now()
+            .signed_duration_since(self.fetched_at)
+            .to_std()
+            .map_or(true, |age| age < ttl)
+    }
+}
+
+/// Decodes a response body with the charset named by `content_type`, or as UTF-8 when it
+/// names none or one that is unknown. A byte order mark takes precedence over both.
+pub fn decode_body(content_type: Option<&str>, body: &[u8]) -> String {
+    let encoding = content_type
+        .into_iter()
+        .flat_map(|value| value.split(';').skip(1))
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+        .and_then(|(_, label)| Encoding::for_label(label.trim().trim_matches('"').as_bytes()))
+        .unwrap_or(UTF_8);
+    encoding.decode(body).0.into_owned()
+}
+
+/// One JSON file per URL, named after the hash of the normalized URL.
+pub struct HttpCache {
+    dir: PathBuf,
+}
+
+impl HttpCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// The `http` directory under `utils::cache_dir`.
+    pub fn from_env() -> Self {
+        Self::new(cache_dir().join("http"))
+    }
+
+    /// The stored response for `url`; unreadable entries count as missing.
+    pub async fn get(&self, url: &Url) -> Option<CachedResponse> {
+        let entry = tokio::fs::read(self.path(url)).await.ok()?;
+        match serde_json::from_slice(&entry) {
+            Ok(response) => Some(response),
+            Err(e) => {
+                log::warn!("Ignoring corrupt cache entry for {}: {}", url, e);
+                None
+            }
+        }
+    }
+
+    pub async fn put(&self, response: &CachedResponse) -> Result<(), AppError> {
+        let url = Url::parse(&response.url)
+            .map_err(|e| AppError::InvalidParams(format!("url '{}': {}", response.url, e)))?;
+        write_atomically(&self.path(&url), &serde_json::to_vec(response)?).await
+    }
+
+    fn path(&self, url: &Url) -> PathBuf {
+        let key = Sha256::digest(normalize_url(url).as_bytes());
+        self.dir.join(format!("{:x}.json", key))
+    }
+}
+
+/// `url` without its fragment and with its query parameters sorted, so that URLs that
+/// name the same resource share an entry.
+fn normalize_url(url: &Url) -> String {
+    let mut url = url.clone();
+    url.set_fragment(None);
+
+    let mut query: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+    if query.is_empty() {
+        url.set_query(None);
+    } else {
+        query.sort();
+        url.query_pairs_mut().clear().extend_pairs(query);
+    }
+    url.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_equivalent_urls() {
+        let url = |url: &str| normalize_url(&Url::parse(url).unwrap());
+
+        assert_eq!(
+            url("HTTPS://Example.com:443/list?b=2&a=1#top"),
+            url("https://example.com/list?a=1&b=2")
+        );
+        assert_eq!(url("https://example.com/list?"), "https://example.com/list");
+        assert_ne!(
+            url("https://example.com/list?a=1"),
+            url("https://example.com/list?a=2")
+        );
+    }
+
+    #[tokio::test]
+    async fn stores_and_expires_responses() {
+        let dir = std::env::temp_dir().join(format!("http-cache-{}", uuid::Uuid::new_v4()));
+        let cache = HttpCache::new(&dir);
+        let url = Url::parse("https://example.com/page?b=2&a=1").unwrap();
+        assert!(cache.get(&url).await.is_none());
+
+        let mut headers = HeaderMap::new();
+        headers.insert("ETag", "\"v1\"".parse().unwrap());
+        let mut response = CachedResponse::new(&url, 200, &headers, "<p>hi</p>".to_string());
+        cache.put(&response).await.unwrap();
+
+        let cached = cache
+            .get(&Url::parse("https://example.com/page?a=1&b=2#x").unwrap())
+            .await
+            .unwrap();
+        assert_eq!(cached.body, "<p>hi</p>");
+        assert_eq!(cached.header("etag"), Some("\"v1\""));
+        assert!(cached.is_fresh(Duration::from_secs(60)));
+
+        response.fetched_at -= chrono::Duration::seconds(120);
+        assert!(!response.is_fresh(Duration::from_secs(60)));
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
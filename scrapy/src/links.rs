@@ -0,0 +1,202 @@
+use std::collections::HashSet;
+
+use regex::Regex;
+use scraper::{Html, Selector};
+use url::Url;
+
+use crate::{
+    error::AppError,
+    models::{LinkScope, ScrapeParams},
+};
+
+/// Decides which links discovered on a page are handed back to the crawler.
+pub struct LinkFilter {
+    start_url: Url,
+    scope: LinkScope,
+    prefix: String,
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
+
+impl LinkFilter {
+    pub fn new(params: &ScrapeParams) -> Result<Self, AppError> {
+        let start_url = Url::parse(&params.url)
+            .map_err(|e| AppError::InvalidParams(format!("url '{}': {}", params.url, e)))?;
+
+        let prefix = params
+            .link_prefix
+            .clone()
+            .unwrap_or_else(|| start_url.to_string());
+
+        Ok(Self {
+            start_url,
+            scope: params.link_scope,
+            prefix,
+            include: compile_patterns(&params.include_patterns)?,
+            exclude: compile_patterns(&params.exclude_patterns)?,
+        })
+    }
+
+    pub fn allows(&self, url: &Url) -> bool {
+        if !matches!(url.scheme(), "http" | "https") {
+            return false;
+        }
+
+        let in_scope = match self.scope {
+            LinkScope::SameHost => {
+                url.host_str() == self.start_url.host_str()
+                    && url.port_or_known_default() == self.start_url.port_or_known_default()
+            }
+            LinkScope::SameDomain => match (url.host_str(), self.start_url.host_str()) {
+                (Some(host), Some(start_host)) => {
+                    let domain = start_host.strip_prefix("www.").unwrap_or(start_host);
+                    host == domain || host.ends_with(&format!(".{}", domain))
+                }
+                _ => false,
+            },
+            LinkScope::Prefix => url.as_str().starts_with(&self.prefix),
+            LinkScope::Any => true,
+        };
+
+        in_scope
+            && (self.include.is_empty() || self.include.iter().any(|re| re.is_match(url.as_str())))
+            && !self.exclude.iter().any(|re| re.is_match(url.as_str()))
+    }
+}
+
+fn compile_patterns(patterns: &[String]) -> Result<Vec<Regex>, AppError> {
+    patterns
+        .iter()
+        .map(|p| {
+            Regex::new(p).map_err(|e| AppError::InvalidParams(format!("pattern '{}': {}", p, e)))
+        })
+        .collect()
+}
+
+/// Extracts the absolute, fragment-free targets of every `<a href>` in the document,
+/// resolved against `<base href>` when present and against `page_url` otherwise.
+pub fn extract_links(document: &Html, page_url: &Url) -> Vec<Url> {
+    let anchor_selector = Selector::parse("a[href]").unwrap();
+    let base = document_base(document, page_url);
+
+    let mut seen = HashSet::new();
+    let mut links = Vec::new();
+
+    for element in document.select(&anchor_selector) {
+        let Some(href) = element.value().attr("href").map(str::trim) else {
+            continue;
+        };
+
+        if href.is_empty() || href.starts_with('#') {
+            continue;
+        }
+
+        if let Ok(mut url) = base.join(href) {
+            url.set_fragment(None);
+            if seen.insert(url.to_string()) {
+                links.push(url);
+            }
+        }
+    }
+
+    links
+}
+
+/// Returns the URL relative links resolve against: `<base href>` if present, else `page_url`.
+pub fn document_base(document: &Html, page_url: &Url) -> Url {
+    let base_selector = Selector::parse("base[href]").unwrap();
+
+    document
+        .select(&base_selector)
+        .next()
+        .and_then(|element| element.value().attr("href"))
+        .and_then(|href| page_url.join(href).ok())
+        .unwrap_or_else(|| page_url.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(scope: LinkScope) -> ScrapeParams {
+        let mut params = ScrapeParams::for_test("https://www.example.com/shop/");
+        params.follow_links = true;
+        params.link_scope = scope;
+        params
+    }
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    #[test]
+    fn extracts_and_resolves_links() {
+        let html = Html::parse_document(
+            r##"<a href="/a">A</a>
+                <a href="b?x=1#frag">B</a>
+                <a href="#top">Top</a>
+                <a href="/a#again">A again</a>
+                <a href="https://other.org/c">C</a>"##,
+        );
+
+        let links: Vec<String> = extract_links(&html, &url("https://www.example.com/shop/"))
+            .into_iter()
+            .map(String::from)
+            .collect();
+
+        assert_eq!(
+            links,
+            vec![
+                "https://www.example.com/a",
+                "https://www.example.com/shop/b?x=1",
+                "https://other.org/c",
+            ]
+        );
+    }
+
+    #[test]
+    fn honours_base_href() {
+        let html = Html::parse_document(
+            r#"<head><base href="https://cdn.example.com/root/"></head><a href="page">P</a>"#,
+        );
+
+        let links = extract_links(&html, &url("https://www.example.com/"));
+
+        assert_eq!(links, vec![url("https://cdn.example.com/root/page")]);
+    }
+
+    #[test]
+    fn scopes_links() {
+        let same_host = LinkFilter::new(&params(LinkScope::SameHost)).unwrap();
+        assert!(same_host.allows(&url("https://www.example.com/other")));
+        assert!(!same_host.allows(&url("https://blog.example.com/")));
+        assert!(!same_host.allows(&url("mailto:someone@example.com")));
+
+        let same_domain = LinkFilter::new(&params(LinkScope::SameDomain)).unwrap();
+        assert!(same_domain.allows(&url("https://blog.example.com/")));
+        assert!(same_domain.allows(&url("https://example.com/")));
+        assert!(!same_domain.allows(&url("https://notexample.com/")));
+
+        let prefix = LinkFilter::new(&params(LinkScope::Prefix)).unwrap();
+        assert!(prefix.allows(&url("https://www.example.com/shop/item/1")));
+        assert!(!prefix.allows(&url("https://www.example.com/about")));
+    }
+
+    #[test]
+    fn applies_include_and_exclude_patterns() {
+        let mut p = params(LinkScope::Any);
+        p.include_patterns = vec![r"/item/\d+".to_string()];
+        p.exclude_patterns = vec![r"/item/13$".to_string()];
+        let filter = LinkFilter::new(&p).unwrap();
+
+        assert!(filter.allows(&url("https://www.example.com/item/12")));
+        assert!(!filter.allows(&url("https://www.example.com/item/13")));
+        assert!(!filter.allows(&url("https://www.example.com/about")));
+
+        p.include_patterns = vec!["(".to_string()];
+        assert!(matches!(
+            LinkFilter::new(&p),
+            Err(AppError::InvalidParams(_))
+        ));
+    }
+}
@@ -1,20 +1,40 @@
 use crate::crawler::Crawler;
 use rocket::{fs::FileServer, routes};
 use rocket_cors::{AllowedHeaders, AllowedOrigins};
-use services::{AIService, CrawlerService, GeminiAIProvider, WebSocketService};
+use services::{AIService, CrawlerService, JobService, WebSocketService};
 use std::sync::Arc;
 use std::time::Duration;
+use store::Store;
 use utils::find_static_dir;
 
 mod ai;
+mod budget;
+mod chunk;
+mod clean;
 mod constants;
 mod crawler;
 mod error;
+mod export;
+mod extract;
+mod http_cache;
+mod links;
 mod models;
+mod pagination;
+mod pricing;
+mod progress;
+mod retry;
+mod robots;
 mod routes;
+mod scheduler;
+mod schema;
 mod services;
 mod spider;
+mod store;
+#[cfg(test)]
+mod test_support;
 mod utils;
+mod warc;
+mod xpath;
 
 #[rocket::launch]
 fn rocket() -> _ {
@@ -22,23 +42,36 @@ fn rocket() -> _ {
 
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
+    match pricing::reload() {
+        Ok(table) => log::info!("Loaded pricing for {} models", table.len()),
+        Err(e) => log::error!(
+            "Failed to load pricing config, using built-in prices: {}",
+            e
+        ),
+    }
+
     let websocket_service = Arc::new(WebSocketService::new(1024));
-    let gemini_provider = GeminiAIProvider::new();
-    let ai_service = Arc::new(AIService::new(gemini_provider));
+    let ai_service = Arc::new(AIService::from_env());
+
+    let store = Arc::new(Store::from_env().expect("Failed to open the crawl store"));
 
-    let crawler = Crawler::new(Duration::from_millis(200), 2, 500);
-    let crawler_service = Arc::new(CrawlerService::new(
-        crawler,
-        websocket_service.clone(),
-        ai_service.clone(),
-    ));
+    let crawler = Crawler::new(Duration::from_millis(200), 2, 8, 500);
+    let crawler_service = Arc::new(
+        CrawlerService::new(crawler, websocket_service.clone(), ai_service.clone())
+            .with_store(store.clone()),
+    );
+    let job_service = Arc::new(JobService::new(crawler_service.clone()));
 
     let cors = rocket_cors::CorsOptions {
         allowed_origins: AllowedOrigins::all(),
-        allowed_methods: vec![rocket::http::Method::Get, rocket::http::Method::Post]
-            .into_iter()
-            .map(From::from)
-            .collect(),
+        allowed_methods: vec![
+            rocket::http::Method::Get,
+            rocket::http::Method::Post,
+            rocket::http::Method::Delete,
+        ]
+        .into_iter()
+        .map(From::from)
+        .collect(),
         allowed_headers: AllowedHeaders::some(&["Authorization", "Accept", "Content-Type"]),
         allow_credentials: true,
         ..Default::default()
@@ -60,14 +93,25 @@ fn rocket() -> _ {
             routes![
                 routes::index,
                 routes::crawl,
+                routes::create_crawl,
+                routes::get_crawl,
+                routes::get_crawl_results,
+                routes::cancel_crawl,
                 routes::websocket,
                 routes::sse_events,
-                routes::get_models
+                routes::get_models,
+                routes::get_pricing,
+                routes::reload_pricing,
+                routes::list_crawls,
+                routes::list_pages,
+                routes::list_records
             ],
         )
         .mount("/", FileServer::from(static_dir))
         .manage(websocket_service)
         .manage(crawler_service)
+        .manage(job_service)
         .manage(ai_service)
+        .manage(store)
         .attach(cors)
 }
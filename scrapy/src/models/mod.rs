@@ -1,58 +1,614 @@
+use std::collections::BTreeMap;
+
 use chrono::{DateTime, Utc};
+use rocket::FromForm;
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::chunk::ChunkingOptions;
+use crate::clean::CleaningOptions;
+use crate::http_cache::CacheOptions;
+use crate::retry::RetryPolicy;
+use crate::warc::WarcOptions;
 
 mod message;
 
 pub use message::*;
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct ScrapeParams {
     pub model: String,
+    /// Never serialized, so that stored crawl parameters leave it out.
+    #[serde(skip_serializing)]
     pub api_key: String,
     pub url: String,
     pub enable_scraping: bool,
     pub tags: Vec<String>,
+    /// JSON Schema describing one extracted record. When set, `tags` are ignored, the
+    /// provider is asked for records following it and every record is validated against it.
+    #[serde(default)]
+    pub schema: Option<serde_json::Value>,
+    /// Extracts records with CSS selectors or XPath instead of the AI model when set, at no
+    /// token cost.
+    #[serde(default)]
+    pub extraction: Option<SelectorExtraction>,
+    /// How pages are cleaned before they are sent to the AI model.
+    #[serde(default)]
+    pub cleaning: CleaningOptions,
+    /// How pages too large for one AI request are split.
+    #[serde(default)]
+    pub chunking: ChunkingOptions,
+    /// Most the crawl may spend on AI requests, in US dollars. Unlimited when unset.
+    #[serde(default)]
+    pub max_cost_usd: Option<f64>,
+    /// Most input and output tokens the crawl may use. Unlimited when unset.
+    #[serde(default)]
+    pub max_tokens: Option<u64>,
     pub enable_pagination: bool,
     pub pagination_details: Option<String>,
+    /// Upper bound on pages followed through "next page" links, including the start page.
+    #[serde(default)]
+    pub max_pagination_pages: Option<usize>,
+    /// Deepest link level queued, counting the start URL as depth 0. Unlimited when unset.
+    #[serde(default)]
+    pub max_depth: Option<usize>,
+    /// Total number of pages the crawl may queue, including the start URL. Unlimited when unset.
+    #[serde(default)]
+    pub max_pages: Option<usize>,
+    /// Minimum milliseconds between requests to the same host; overrides the server default.
+    #[serde(default)]
+    pub request_interval_ms: Option<u64>,
+    /// Requests allowed in flight per host; overrides the server default.
+    #[serde(default)]
+    pub max_requests_per_host: Option<usize>,
+    /// How failed page fetches are retried.
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    /// Whether fetched pages are stored on disk and served again on later crawls.
+    #[serde(default)]
+    pub cache: CacheOptions,
+    /// How the crawl uses stored AI responses.
+    #[serde(default)]
+    pub ai_cache: AiCacheMode,
+    /// Records the crawl's traffic to a WARC file, or replays it from one. The page and AI
+    /// response caches are not used while recording or replaying.
+    #[serde(default)]
+    pub warc: Option<WarcOptions>,
+    /// Response status classes whose bodies are scraped; others fail the page with
+    /// `AppError::HttpStatus`. Defaults to `success` only when unset.
+    #[serde(default)]
+    pub accepted_status_classes: Option<Vec<StatusClass>>,
+    /// User-agent sent with requests and matched against robots.txt groups.
+    #[serde(default)]
+    pub user_agent: Option<String>,
+    /// Queue links discovered on each page in addition to the start URL.
+    #[serde(default)]
+    pub follow_links: bool,
+    #[serde(default)]
+    pub link_scope: LinkScope,
+    /// URL prefix used by `LinkScope::Prefix`; defaults to the start URL.
+    #[serde(default)]
+    pub link_prefix: Option<String>,
+    /// Regex patterns a discovered link must match (any of) to be followed.
+    #[serde(default)]
+    pub include_patterns: Vec<String>,
+    /// Regex patterns that exclude a discovered link when matched.
+    #[serde(default)]
+    pub exclude_patterns: Vec<String>,
 }
 
-#[derive(Serialize)]
+#[cfg(test)]
+impl ScrapeParams {
+    pub fn for_test(url: &str) -> Self {
+        serde_json::from_value(serde_json::json!({
+            "model": "gemini-1.5-flash-latest",
+            "apiKey": "",
+            "url": url,
+            "enableScraping": false,
+            "tags": [],
+            "enablePagination": false,
+        }))
+        .unwrap()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum LinkScope {
+    /// Only links on exactly the same host as the start URL.
+    #[default]
+    SameHost,
+    /// Links on the start URL's domain or any of its subdomains.
+    SameDomain,
+    /// Links whose URL starts with `link_prefix`.
+    Prefix,
+    /// Any http(s) link.
+    Any,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum AiCacheMode {
+    /// Reuse a stored response for an identical request, and store new responses.
+    #[default]
+    Use,
+    /// Neither read nor store responses.
+    Bypass,
+    /// Always call the provider, replacing stored responses.
+    Refresh,
+}
+
+/// Maps record fields to CSS selectors or XPath 1.0 expressions.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SelectorExtraction {
+    /// Selector for the elements that each hold one record. The whole page is a single
+    /// record when neither this nor `itemXPath` is set.
+    #[serde(default)]
+    pub item_selector: Option<String>,
+    /// XPath for the elements that each hold one record, instead of `itemSelector`.
+    #[serde(default, rename = "itemXPath")]
+    pub item_xpath: Option<String>,
+    pub fields: BTreeMap<String, FieldSelector>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldSelector {
+    /// Selector relative to the record's element; the element itself when neither this
+    /// nor `xpath` is set.
+    #[serde(default)]
+    pub selector: Option<String>,
+    /// XPath relative to the record's element, instead of `selector`. Matches that are not
+    /// elements, like `@href`, `text()` or `count(li)`, give their value as is.
+    #[serde(default)]
+    pub xpath: Option<String>,
+    #[serde(default)]
+    pub extract: ExtractKind,
+    /// Attribute read by `ExtractKind::Attribute`.
+    #[serde(default)]
+    pub attribute: Option<String>,
+    /// Collect every match into an array instead of taking the first one.
+    #[serde(default)]
+    pub multiple: bool,
+    /// Applied in order to each extracted value.
+    #[serde(default)]
+    pub transforms: Vec<Transform>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ExtractKind {
+    /// The element's text content.
+    #[default]
+    Text,
+    /// The element's inner HTML.
+    InnerHtml,
+    /// The element's HTML, including its own tag.
+    Html,
+    Attribute,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Transform {
+    /// Trims surrounding whitespace and collapses runs of whitespace to one space.
+    Trim,
+    /// Keeps capture group `group` of the first match, or the first capture group (the
+    /// whole match if there is none) when unset. No match gives `null`.
+    Regex {
+        pattern: String,
+        #[serde(default)]
+        group: Option<usize>,
+    },
+    /// Parses the one number in the text, ignoring currency symbols and thousands
+    /// separators. Gives `null` when there is no number, several, or when `1,299` could
+    /// be read either way and `decimalSeparator` is unset.
+    #[serde(rename_all = "camelCase")]
+    Number {
+        /// `.` or `,`; guessed from the text when unset.
+        #[serde(default)]
+        decimal_separator: Option<char>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum StatusClass {
+    /// 1xx
+    Informational,
+    /// 2xx
+    Success,
+    /// 3xx responses that were not followed, e.g. `304` or a redirect without `Location`.
+    Redirection,
+    /// 4xx
+    ClientError,
+    /// 5xx
+    ServerError,
+}
+
+impl StatusClass {
+    pub fn of(status: u16) -> Option<Self> {
+        match status / 100 {
+            1 => Some(Self::Informational),
+            2 => Some(Self::Success),
+            3 => Some(Self::Redirection),
+            4 => Some(Self::ClientError),
+            5 => Some(Self::ServerError),
+            _ => None,
+        }
+    }
+}
+
+impl ScrapeParams {
+    pub fn accepts_status(&self, status: u16) -> bool {
+        let Some(class) = StatusClass::of(status) else {
+            return false;
+        };
+
+        match &self.accepted_status_classes {
+            Some(classes) => classes.contains(&class),
+            None => class == StatusClass::Success,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct ScrapingResult {
+    pub url: String,
     pub all_data: Vec<serde_json::Value>,
     pub input_tokens: u64,
     pub output_tokens: u64,
     pub total_cost: f64,
     pub pagination_info: Option<PaginationInfo>,
+    /// Why the page was not fetched, e.g. because robots.txt disallows it.
+    pub skipped: Option<String>,
+    /// Fetch attempts made for the page, including retries.
+    pub attempts: u32,
+    /// Why fetching the page failed after all attempts.
+    pub error: Option<String>,
+    /// URL the page was finally fetched from, after redirects.
+    pub final_url: Option<String>,
+    /// HTTP status of the final response.
+    pub status: Option<u16>,
+    /// URLs that redirected, in the order they were visited; excludes `final_url`.
+    pub redirect_chain: Vec<String>,
+    /// Whether the page came from the page cache; unset when `ScrapeParams::cache` is off.
+    pub cache: Option<CacheStatus>,
+    /// Records in `all_data` that do not match `ScrapeParams::schema`.
+    pub validation_errors: Vec<RecordValidation>,
+    /// Size of the content sent to the AI model, before and after cleaning.
+    pub content_size: Option<ContentSize>,
+    /// Usage of every AI request made for the page, including pagination resolution.
+    pub page_usage: UsageMetadata,
+    /// Usage of the whole crawl so far.
+    pub crawl_usage: UsageMetadata,
+    /// The crawl stopped early because its budget ran out.
+    pub budget_limited: bool,
 }
 
-#[derive(Serialize)]
+/// Bytes of a page's selected HTML before and after `ScrapeParams::cleaning`.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContentSize {
+    pub original_bytes: usize,
+    pub cleaned_bytes: usize,
+}
+
+/// Why the record at `index` of an extraction result does not match the schema.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordValidation {
+    pub index: usize,
+    pub errors: Vec<String>,
+}
+
+/// Where a page's final response came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CacheStatus {
+    /// Served from the cache without a request.
+    Hit,
+    /// Served from the cache after the site answered that it had not changed.
+    Revalidated,
+    /// Downloaded, and stored for next time.
+    Miss,
+}
+
+/// The outcome of fetching one page.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageMetadata {
+    pub url: String,
+    pub attempts: u32,
+    pub error: Option<String>,
+    pub final_url: Option<String>,
+    pub status: Option<u16>,
+    pub redirect_chain: Vec<String>,
+    pub cache: Option<CacheStatus>,
+    /// Usage of every AI request made for the page.
+    pub usage: UsageMetadata,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SkippedPage {
+    pub url: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct PaginationInfo {
     pub page_urls: Vec<String>,
     pub token_counts: UsageMetadata,
+    /// Usage of each entry in `page_urls`, in the same order.
+    pub page_token_counts: Vec<UsageMetadata>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+impl PaginationInfo {
+    /// Counts each page's `PageMetadata::usage`, which includes resolving its next page.
+    pub fn new(page_urls: Vec<String>, pages: &[PageMetadata]) -> Self {
+        let mut token_counts = UsageMetadata::default();
+        let mut page_token_counts = Vec::with_capacity(page_urls.len());
+
+        for url in &page_urls {
+            let mut usage = UsageMetadata::default();
+            for page in pages.iter().filter(|page| &page.url == url) {
+                usage.add(&page.usage);
+            }
+            token_counts.add(&usage);
+            page_token_counts.push(usage);
+        }
+
+        Self {
+            page_urls,
+            token_counts,
+            page_token_counts,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct UsageMetadata {
     pub input_tokens: u64,
     pub output_tokens: u64,
     pub total_cost: f64,
+    /// AI requests answered from the AI response cache; they use no tokens.
+    pub cache_hits: u64,
+    /// AI requests sent to the provider because the cache had no answer for them.
+    pub cache_misses: u64,
+    /// What the requests answered from the cache would cost at current prices.
+    pub saved_cost: f64,
+}
+
+impl UsageMetadata {
+    pub fn add(&mut self, other: &UsageMetadata) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.total_cost += other.total_cost;
+        self.cache_hits += other.cache_hits;
+        self.cache_misses += other.cache_misses;
+        self.saved_cost += other.saved_cost;
+    }
 }
 
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct AiScrapingResult {
+    pub url: String,
     pub model: String,
     pub start_time: DateTime<Utc>,
     pub end_time: Option<DateTime<Utc>>,
     pub data: serde_json::Value,
     pub usage_metadata: UsageMetadata,
+    /// Only filled when extracting with a schema; records that validate are not listed.
+    pub validation_errors: Vec<RecordValidation>,
+    /// Only set for results extracted from cleaned page content.
+    pub content_size: Option<ContentSize>,
+}
+
+impl AiScrapingResult {
+    /// The extracted records: the elements of an array answer, nothing for `null`, and
+    /// any other answer as a single record.
+    pub fn records(&self) -> &[serde_json::Value] {
+        match &self.data {
+            serde_json::Value::Array(records) => records,
+            serde_json::Value::Null => &[],
+            record => std::slice::from_ref(record),
+        }
+    }
+}
+
+/// Everything a finished crawl produced.
+#[derive(Debug, Clone)]
+pub struct CrawlReport {
+    pub results: Vec<AiScrapingResult>,
+    pub pagination: Option<PaginationInfo>,
+    pub skipped: Vec<SkippedPage>,
+    pub pages: Vec<PageMetadata>,
+    /// Usage of every AI request made during the crawl.
+    pub usage: UsageMetadata,
+    /// The crawl stopped early because `max_cost_usd` or `max_tokens` ran out.
+    pub budget_limited: bool,
+}
+
+impl CrawlReport {
+    pub fn into_scraping_results(self) -> Vec<ScrapingResult> {
+        let mut output = Vec::new();
+
+        for page in self.pages {
+            let page_result = ScrapingResult {
+                url: page.url,
+                pagination_info: self.pagination.clone(),
+                attempts: page.attempts,
+                error: page.error,
+                final_url: page.final_url,
+                status: page.status,
+                redirect_chain: page.redirect_chain,
+                cache: page.cache,
+                page_usage: page.usage,
+                crawl_usage: self.usage.clone(),
+                budget_limited: self.budget_limited,
+                ..Default::default()
+            };
+
+            let mut ai_results = self
+                .results
+                .iter()
+                .filter(|r| r.url == page_result.url)
+                .peekable();
+
+            if ai_results.peek().is_none() {
+                output.push(page_result);
+                continue;
+            }
+
+            for r in ai_results {
+                output.push(ScrapingResult {
+                    all_data: r.records().to_vec(),
+                    input_tokens: r.usage_metadata.input_tokens,
+                    output_tokens: r.usage_metadata.output_tokens,
+                    total_cost: r.usage_metadata.total_cost,
+                    validation_errors: r.validation_errors.clone(),
+                    content_size: r.content_size,
+                    ..page_result.clone()
+                });
+            }
+        }
+
+        output.extend(self.skipped.into_iter().map(|page| ScrapingResult {
+            url: page.url,
+            skipped: Some(page.reason),
+            crawl_usage: self.usage.clone(),
+            budget_limited: self.budget_limited,
+            ..Default::default()
+        }));
+
+        output
+    }
+}
+
+/// Running totals of a crawl's page and extraction events.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProgressCounters {
+    pub queued: usize,
+    pub fetched: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    pub extracted: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum JobStatus {
+    Running,
+    /// Cancellation was requested and the crawl is draining its queues.
+    Cancelling,
+    Cancelled,
+    Completed,
+    /// The crawl was rejected before it started, e.g. for invalid parameters.
+    Failed,
 }
 
-#[derive(Clone, Copy)]
+/// A crawl job as reported by `GET /api/crawls/<id>`; `results` are partial while running.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CrawlJobInfo {
+    pub id: Uuid,
+    pub url: String,
+    pub status: JobStatus,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub counters: ProgressCounters,
+    pub usage: UsageMetadata,
+    pub results: Vec<ScrapingResult>,
+}
+
+/// A crawl saved in the store, as listed by `GET /api/crawls`.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct StoredCrawl {
+    pub id: Uuid,
+    pub url: String,
+    /// The crawl's `ScrapeParams`, without the API key.
+    pub params: serde_json::Value,
+    pub status: JobStatus,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Unset until the crawl finishes.
+    pub usage: Option<UsageMetadata>,
+    pub budget_limited: bool,
+    /// Why the crawl failed, when its status is `failed`.
+    pub error: Option<String>,
+    pub pages: u64,
+    pub records: u64,
+}
+
+/// A page a stored crawl fetched or skipped, as listed by `GET /api/pages`.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct StoredPage {
+    pub crawl_id: Uuid,
+    pub url: String,
+    pub final_url: Option<String>,
+    pub status: Option<u16>,
+    pub attempts: u32,
+    pub error: Option<String>,
+    pub skipped: Option<String>,
+    pub redirect_chain: Vec<String>,
+    pub usage: UsageMetadata,
+}
+
+/// One extracted record of a stored crawl, as listed by `GET /api/records`.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct StoredRecord {
+    pub id: i64,
+    pub crawl_id: Uuid,
+    /// Page the record was extracted from.
+    pub url: String,
+    pub data: serde_json::Value,
+    pub extracted_at: DateTime<Utc>,
+}
+
+/// Query parameters shared by the store's list endpoints. `url` matches URLs starting with
+/// it; `since` and `until` are RFC 3339 times, compared with when crawls started and when
+/// records were extracted.
+#[derive(FromForm, Debug, Default)]
+pub struct StoreQuery {
+    pub crawl: Option<Uuid>,
+    pub url: Option<String>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+}
+
+/// Prices of a model in USD per million tokens.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
 pub struct PricingInfo {
     pub input: f64,
     pub output: f64,
+    /// Price of input tokens served from the provider's prompt cache; `input` when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cached_input: Option<f64>,
+    /// Rates that replace the ones above for requests with a long input.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub long_context: Option<LongContextPricing>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LongContextPricing {
+    /// Requests with more input tokens than this are billed at these rates.
+    pub above_input_tokens: u64,
+    pub input: f64,
+    pub output: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cached_input: Option<f64>,
 }
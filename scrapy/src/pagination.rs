@@ -0,0 +1,187 @@
+use scraper::{ElementRef, Html, Selector};
+use url::Url;
+
+use crate::links::document_base;
+
+/// Selectors for markup that explicitly declares the next page.
+const REL_NEXT_SELECTORS: &[&str] = &["link[rel~=next][href]", "a[rel~=next][href]"];
+
+/// Selectors for the "next" control of common pager widgets.
+const PAGER_SELECTORS: &[&str] = &[
+    ".pagination a.next",
+    ".pagination .next a",
+    ".pager .next a",
+    ".pager-next a",
+    "li.next a",
+    "a.next",
+    "a.next-page",
+    "a.pagination-next",
+    ".nav-next a",
+    "a[aria-label*=next i]",
+];
+
+/// Anchor texts that commonly label a "next page" link, compared after lowercasing.
+const NEXT_LINK_TEXTS: &[&str] = &[
+    "next",
+    "next page",
+    "next »",
+    "next ›",
+    "›",
+    "»",
+    "→",
+    "older posts",
+];
+
+/// A link on the page offered to the AI provider when resolving `pagination_details`.
+#[derive(Debug, Clone)]
+pub struct LinkCandidate {
+    pub text: String,
+    pub href: String,
+}
+
+/// Returns the page declared by `<link rel="next">` or `<a rel="next">`.
+pub fn find_rel_next(document: &Html, page_url: &Url) -> Option<Url> {
+    find_by_selectors(document, page_url, REL_NEXT_SELECTORS)
+}
+
+/// Returns the target of a pager's "next" control, matched by common class names
+/// first and by anchor text second.
+pub fn find_pager_next(document: &Html, page_url: &Url) -> Option<Url> {
+    find_by_selectors(document, page_url, PAGER_SELECTORS).or_else(|| {
+        let base = document_base(document, page_url);
+        let anchor_selector = Selector::parse("a[href]").unwrap();
+
+        document
+            .select(&anchor_selector)
+            .filter(|element| {
+                let text = element_text(element).to_lowercase();
+                NEXT_LINK_TEXTS.contains(&text.as_str())
+            })
+            .find_map(|element| resolve(&base, page_url, element))
+    })
+}
+
+/// Lists up to `limit` distinct links on the page with their visible text.
+pub fn link_candidates(document: &Html, page_url: &Url, limit: usize) -> Vec<LinkCandidate> {
+    let base = document_base(document, page_url);
+    let anchor_selector = Selector::parse("a[href]").unwrap();
+    let mut candidates: Vec<LinkCandidate> = Vec::new();
+
+    for element in document.select(&anchor_selector) {
+        if candidates.len() >= limit {
+            break;
+        }
+
+        if let Some(url) = resolve(&base, page_url, element) {
+            let href = url.to_string();
+            if !candidates.iter().any(|c| c.href == href) {
+                let mut text = element_text(&element);
+                if text.is_empty() {
+                    text = element
+                        .value()
+                        .attr("aria-label")
+                        .unwrap_or_default()
+                        .to_string();
+                }
+                candidates.push(LinkCandidate { text, href });
+            }
+        }
+    }
+
+    candidates
+}
+
+fn find_by_selectors(document: &Html, page_url: &Url, selectors: &[&str]) -> Option<Url> {
+    let base = document_base(document, page_url);
+
+    selectors.iter().find_map(|selector| {
+        let selector = Selector::parse(selector).unwrap();
+        document
+            .select(&selector)
+            .find_map(|element| resolve(&base, page_url, element))
+    })
+}
+
+fn resolve(base: &Url, page_url: &Url, element: ElementRef) -> Option<Url> {
+    let href = element.value().attr("href")?.trim();
+    if href.is_empty() || href.starts_with('#') {
+        return None;
+    }
+
+    let mut url = base.join(href).ok()?;
+    url.set_fragment(None);
+
+    let is_http = matches!(url.scheme(), "http" | "https");
+    (is_http && url != *page_url).then_some(url)
+}
+
+fn element_text(element: &ElementRef) -> String {
+    element
+        .text()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    #[test]
+    fn prefers_rel_next() {
+        let html = Html::parse_document(
+            r#"<head><link rel="next" href="?page=3"></head>
+               <a class="next" href="?page=99">Next</a>"#,
+        );
+
+        let page = url("https://example.com/list?page=2");
+
+        assert_eq!(
+            find_rel_next(&html, &page),
+            Some(url("https://example.com/list?page=3"))
+        );
+    }
+
+    #[test]
+    fn finds_pager_links_by_class_and_text() {
+        let page = url("https://example.com/list");
+
+        let by_class = Html::parse_document(
+            r#"<ul class="pagination"><li><a href="/list?p=1">1</a></li><li><a class="next" href="/list?p=2">→</a></li></ul>"#,
+        );
+        assert_eq!(
+            find_pager_next(&by_class, &page),
+            Some(url("https://example.com/list?p=2"))
+        );
+
+        let by_text =
+            Html::parse_document(r#"<a href="/a">About</a><a href="/list/2"> Next  Page </a>"#);
+        assert_eq!(
+            find_pager_next(&by_text, &page),
+            Some(url("https://example.com/list/2"))
+        );
+
+        let to_self =
+            Html::parse_document(r##"<a class="next" href="/list">Next</a><a href="#">Next</a>"##);
+        assert_eq!(find_pager_next(&to_self, &page), None);
+    }
+
+    #[test]
+    fn lists_distinct_candidates() {
+        let html = Html::parse_document(
+            r#"<a href="/a">A</a><a href="/a">A again</a><a href="/b" aria-label="Load more"></a>"#,
+        );
+
+        let candidates = link_candidates(&html, &url("https://example.com/"), 10);
+
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(candidates[1].text, "Load more");
+        assert_eq!(candidates[1].href, "https://example.com/b");
+    }
+}
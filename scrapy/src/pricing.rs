@@ -0,0 +1,168 @@
+//! The prices used to work out what AI requests cost.
+//!
+//! Prices come from the JSON file named by `PRICING_CONFIG`, an object mapping model names
+//! to `PricingInfo`, with `PRICING_INFO` filling in models the file leaves out. The file is
+//! read at startup and can be read again while the server runs.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{LazyLock, RwLock};
+use std::{env, fs};
+
+use crate::constants::PRICING_INFO;
+use crate::error::AppError;
+use crate::models::PricingInfo;
+
+pub type PricingTable = BTreeMap<String, PricingInfo>;
+
+static PRICING: LazyLock<RwLock<PricingTable>> = LazyLock::new(|| RwLock::new(built_in()));
+
+impl PricingInfo {
+    /// Cost in USD of a request. `cached_input_tokens` are part of `input_tokens`.
+    pub fn cost(&self, input_tokens: u64, cached_input_tokens: u64, output_tokens: u64) -> f64 {
+        let (input, output, cached_input) = match self.long_context {
+            Some(tier) if input_tokens > tier.above_input_tokens => {
+                (tier.input, tier.output, tier.cached_input)
+            }
+            _ => (self.input, self.output, self.cached_input),
+        };
+
+        let cached_tokens = cached_input_tokens.min(input_tokens);
+        let cost = (input_tokens - cached_tokens) as f64 * input
+            + cached_tokens as f64 * cached_input.unwrap_or(input)
+            + output_tokens as f64 * output;
+        cost / 1_000_000.0
+    }
+
+    fn check(&self, model: &str) -> Result<(), AppError> {
+        let mut prices = vec![self.input, self.output];
+        prices.extend(self.cached_input);
+        if let Some(tier) = self.long_context {
+            prices.extend([tier.input, tier.output]);
+            prices.extend(tier.cached_input);
+        }
+
+        if prices
+            .iter()
+            .all(|price| price.is_finite() && *price >= 0.0)
+        {
+            Ok(())
+        } else {
+            Err(AppError::InvalidParams(format!(
+                "pricing for '{}': prices must be non-negative numbers",
+                model
+            )))
+        }
+    }
+}
+
+pub fn get(model: &str) -> Option<PricingInfo> {
+    PRICING.read().unwrap().get(model).copied()
+}
+
+pub fn table() -> PricingTable {
+    PRICING.read().unwrap().clone()
+}
+
+/// Rereads the file named by `PRICING_CONFIG`, or goes back to the built-in prices when it
+/// is unset. The current prices are kept if the file cannot be read.
+pub fn reload() -> Result<PricingTable, AppError> {
+    let path = env::var("PRICING_CONFIG")
+        .ok()
+        .filter(|path| !path.is_empty());
+    let table = load(path.as_deref().map(Path::new))?;
+    *PRICING.write().unwrap() = table.clone();
+    Ok(table)
+}
+
+/// The built-in prices, overridden and extended by the config file at `path`.
+pub fn load(path: Option<&Path>) -> Result<PricingTable, AppError> {
+    let mut table = built_in();
+    let Some(path) = path else {
+        return Ok(table);
+    };
+
+    let config: PricingTable = serde_json::from_str(&fs::read_to_string(path)?)?;
+    for (model, pricing) in &config {
+        pricing.check(model)?;
+    }
+    table.extend(config);
+    Ok(table)
+}
+
+fn built_in() -> PricingTable {
+    PRICING_INFO
+        .entries()
+        .map(|(model, pricing)| (model.to_string(), *pricing))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::LongContextPricing;
+
+    #[test]
+    fn prices_long_context_and_cached_input() {
+        let pricing = PricingInfo {
+            input: 1.0,
+            output: 4.0,
+            cached_input: Some(0.25),
+            long_context: Some(LongContextPricing {
+                above_input_tokens: 100_000,
+                input: 2.0,
+                output: 8.0,
+                cached_input: None,
+            }),
+        };
+
+        assert_eq!(pricing.cost(100_000, 0, 100_000), 0.5);
+        assert_eq!(pricing.cost(100_000, 100_000, 0), 0.025);
+        // Past the threshold every token is billed at the long-context rates.
+        assert_eq!(pricing.cost(200_000, 0, 100_000), 1.2);
+        assert_eq!(pricing.cost(200_000, 100_000, 0), 0.4);
+    }
+
+    #[test]
+    fn loads_config_over_built_in_prices() {
+        let path = env::temp_dir().join(format!("pricing-{}.json", uuid::Uuid::new_v4()));
+        fs::write(
+            &path,
+            r#"{
+                "gemini-1.5-flash-latest": { "input": 0.1, "output": 0.4 },
+                "gemini-2.0-flash": {
+                    "input": 0.1,
+                    "output": 0.4,
+                    "cachedInput": 0.025,
+                    "longContext": { "aboveInputTokens": 128000, "input": 0.2, "output": 0.8 }
+                }
+            }"#,
+        )
+        .unwrap();
+        let table = load(Some(&path));
+
+        fs::write(
+            &path,
+            r#"{ "gemini-2.0-flash": { "input": -1, "output": 0.4 } }"#,
+        )
+        .unwrap();
+        let negative = load(Some(&path));
+        fs::remove_file(&path).unwrap();
+
+        let table = table.unwrap();
+        assert_eq!(table["gemini-1.5-flash-latest"].input, 0.1);
+        assert_eq!(table["gemini-1.5-flash-latest"].long_context, None);
+        assert_eq!(
+            table["gemini-2.0-flash"]
+                .long_context
+                .unwrap()
+                .above_input_tokens,
+            128_000
+        );
+        assert_eq!(table["openai:gpt-4o"], PRICING_INFO["openai:gpt-4o"]);
+
+        assert!(matches!(negative, Err(AppError::InvalidParams(_))));
+        assert!(load(Some(&path)).is_err());
+        assert_eq!(load(None).unwrap(), built_in());
+    }
+}
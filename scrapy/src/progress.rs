@@ -0,0 +1,313 @@
+use std::{
+    fmt::Display,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::{
+    models::{
+        AiScrapingResult, ContentSize, CrawlReport, MessageType, ProgressCounters, UsageMetadata,
+        WebSocketMessage,
+    },
+    services::WebSocketService,
+};
+
+/// Publishes the progress of one crawl to `/api/ws` and `/api/events` subscribers.
+///
+/// Every message's `metadata` carries the crawl id, the event name, the URL it concerns
+/// (if any) and the crawl's counters at the time it was sent.
+pub struct ProgressReporter {
+    crawl_id: Uuid,
+    websocket_service: Option<Arc<WebSocketService>>,
+    queued: AtomicUsize,
+    fetched: AtomicUsize,
+    failed: AtomicUsize,
+    skipped: AtomicUsize,
+    extracted: AtomicUsize,
+    usage: Mutex<UsageMetadata>,
+}
+
+impl ProgressReporter {
+    pub fn new(crawl_id: Uuid, websocket_service: Arc<WebSocketService>) -> Self {
+        Self::with_service(crawl_id, Some(websocket_service))
+    }
+
+    /// A reporter that only keeps counters, for crawls nobody listens to.
+    #[cfg(test)]
+    pub fn silent() -> Self {
+        Self::with_service(Uuid::new_v4(), None)
+    }
+
+    fn with_service(crawl_id: Uuid, websocket_service: Option<Arc<WebSocketService>>) -> Self {
+        Self {
+            crawl_id,
+            websocket_service,
+            queued: AtomicUsize::new(0),
+            fetched: AtomicUsize::new(0),
+            failed: AtomicUsize::new(0),
+            skipped: AtomicUsize::new(0),
+            extracted: AtomicUsize::new(0),
+            usage: Mutex::new(UsageMetadata::default()),
+        }
+    }
+
+    pub fn crawl_id(&self) -> Uuid {
+        self.crawl_id
+    }
+
+    pub async fn url_queued(&self, url: &str, depth: usize) {
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        self.publish(
+            MessageType::Progress,
+            "urlQueued",
+            Some(url),
+            format!("Queued {}", url),
+            json!({ "depth": depth }),
+        )
+        .await;
+    }
+
+    pub async fn url_fetched(&self, url: &str, status: Option<u16>) {
+        self.fetched.fetch_add(1, Ordering::SeqCst);
+        self.publish(
+            MessageType::Progress,
+            "urlFetched",
+            Some(url),
+            format!("Fetched {}", url),
+            json!({ "status": status }),
+        )
+        .await;
+    }
+
+    pub async fn url_failed(&self, url: &str, error: &impl Display) {
+        self.failed.fetch_add(1, Ordering::SeqCst);
+        self.publish(
+            MessageType::Error,
+            "urlFailed",
+            Some(url),
+            format!("Failed to fetch {}: {}", url, error),
+            json!({}),
+        )
+        .await;
+    }
+
+    pub async fn url_skipped(&self, url: &str, reason: &str) {
+        self.skipped.fetch_add(1, Ordering::SeqCst);
+        self.publish(
+            MessageType::Warning,
+            "urlSkipped",
+            Some(url),
+            format!("Skipped {}: {}", url, reason),
+            json!({ "reason": reason }),
+        )
+        .await;
+    }
+
+    pub async fn extraction_started(&self, url: &str, content_size: &ContentSize, chunks: usize) {
+        self.publish(
+            MessageType::Progress,
+            "extractionStarted",
+            Some(url),
+            format!("Extracting data from {}", url),
+            json!({ "contentSize": content_size, "chunks": chunks }),
+        )
+        .await;
+    }
+
+    /// Sends the extracted data as a `scrapingResult` message.
+    pub async fn extraction_finished(&self, result: &AiScrapingResult) {
+        self.extracted.fetch_add(1, Ordering::SeqCst);
+        self.publish(
+            MessageType::ScrapingResult,
+            "extractionFinished",
+            Some(&result.url),
+            result.data.to_string(),
+            json!({
+                "model": result.model,
+                "usage": usage_json(&result.usage_metadata),
+                "invalidRecords": result.validation_errors.len(),
+            }),
+        )
+        .await;
+    }
+
+    pub async fn extraction_failed(&self, url: &str, error: &impl Display) {
+        self.publish(
+            MessageType::Error,
+            "extractionFailed",
+            Some(url),
+            format!("Failed to extract data from {}: {}", url, error),
+            json!({}),
+        )
+        .await;
+    }
+
+    /// Sends every extracted item as a `success` message, in the shape the client
+    /// expects: a JSON array of objects.
+    pub async fn crawl_finished(&self, report: &CrawlReport) {
+        let items: Vec<&Value> = report
+            .results
+            .iter()
+            .flat_map(|result| match &result.data {
+                Value::Array(values) => values.iter().collect(),
+                value => vec![value],
+            })
+            .filter(|value| value.is_object())
+            .collect();
+
+        self.publish(
+            MessageType::Success,
+            "crawlFinished",
+            None,
+            serde_json::to_string(&items).unwrap_or_else(|_| "[]".to_string()),
+            json!({
+                "pages": report.pages.len(),
+                "usage": usage_json(&report.usage),
+                "budgetLimited": report.budget_limited,
+            }),
+        )
+        .await;
+    }
+
+    pub async fn budget_exhausted(&self) {
+        self.publish(
+            MessageType::Warning,
+            "budgetExhausted",
+            None,
+            "Budget exhausted, stopping the crawl".to_string(),
+            json!({}),
+        )
+        .await;
+    }
+
+    pub async fn crawl_cancelled(&self) {
+        self.publish(
+            MessageType::Warning,
+            "crawlCancelled",
+            None,
+            "Crawl cancelled".to_string(),
+            json!({}),
+        )
+        .await;
+    }
+
+    pub async fn crawl_failed(&self, error: &impl Display) {
+        self.publish(
+            MessageType::Error,
+            "crawlFailed",
+            None,
+            format!("Crawl failed: {}", error),
+            json!({}),
+        )
+        .await;
+    }
+
+    /// Adds the usage of one AI request to the crawl's running total.
+    pub fn usage_recorded(&self, usage: &UsageMetadata) {
+        self.usage.lock().unwrap().add(usage);
+    }
+
+    pub fn usage(&self) -> UsageMetadata {
+        self.usage.lock().unwrap().clone()
+    }
+
+    pub fn counters(&self) -> ProgressCounters {
+        ProgressCounters {
+            queued: self.queued.load(Ordering::SeqCst),
+            fetched: self.fetched.load(Ordering::SeqCst),
+            failed: self.failed.load(Ordering::SeqCst),
+            skipped: self.skipped.load(Ordering::SeqCst),
+            extracted: self.extracted.load(Ordering::SeqCst),
+        }
+    }
+
+    async fn publish(
+        &self,
+        r#type: MessageType,
+        event: &str,
+        url: Option<&str>,
+        payload: String,
+        extra: Value,
+    ) {
+        let Some(websocket_service) = &self.websocket_service else {
+            return;
+        };
+
+        let mut metadata = json!({
+            "crawlId": self.crawl_id,
+            "event": event,
+            "url": url,
+            "counters": self.counters(),
+            "crawlUsage": usage_json(&self.usage()),
+        });
+        if let (Some(metadata), Value::Object(extra)) = (metadata.as_object_mut(), extra) {
+            metadata.extend(extra);
+        }
+
+        let message = WebSocketMessage {
+            r#type,
+            payload,
+            metadata: Some(metadata),
+        };
+
+        if let Err(e) = websocket_service.send_message(message).await {
+            log::debug!("Dropping progress message: {}", e);
+        }
+    }
+}
+
+fn usage_json(usage: &UsageMetadata) -> Value {
+    json!({
+        "inputTokens": usage.input_tokens,
+        "outputTokens": usage.output_tokens,
+        "totalCost": usage.total_cost,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn publishes_messages_with_crawl_metadata() {
+        let websocket_service = Arc::new(WebSocketService::new(16));
+        let mut rx = websocket_service.subscribe().await;
+        let progress = ProgressReporter::new(Uuid::new_v4(), websocket_service);
+
+        progress.url_queued("https://example.com/", 0).await;
+        progress.usage_recorded(&UsageMetadata {
+            input_tokens: 100,
+            output_tokens: 10,
+            total_cost: 0.25,
+            ..Default::default()
+        });
+        progress
+            .url_failed("https://example.com/", &"connection refused")
+            .await;
+
+        let queued = rx.recv().await.unwrap();
+        assert_eq!(queued.r#type, MessageType::Progress);
+        let metadata = queued.metadata.unwrap();
+        assert_eq!(metadata["crawlId"], progress.crawl_id().to_string());
+        assert_eq!(metadata["event"], "urlQueued");
+        assert_eq!(metadata["url"], "https://example.com/");
+        assert_eq!(metadata["depth"], 0);
+        assert_eq!(metadata["counters"]["queued"], 1);
+
+        let failed = rx.recv().await.unwrap();
+        assert_eq!(failed.r#type, MessageType::Error);
+        assert_eq!(
+            failed.payload,
+            "Failed to fetch https://example.com/: connection refused"
+        );
+        let metadata = failed.metadata.unwrap();
+        assert_eq!(metadata["counters"]["failed"], 1);
+        assert_eq!(metadata["crawlUsage"]["inputTokens"], 100);
+        assert_eq!(metadata["crawlUsage"]["totalCost"], 0.25);
+    }
+}
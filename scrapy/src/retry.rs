@@ -0,0 +1,249 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use reqwest::{header::RETRY_AFTER, RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use tokio::time::sleep;
+
+/// How page fetches are retried after transient failures.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RetryPolicy {
+    /// Total attempts per URL, including the first one.
+    pub max_attempts: u32,
+    pub base_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    /// Fraction (0.0 to 1.0) of each backoff that is randomized.
+    pub jitter: f64,
+    pub retryable_status_codes: Vec<u16>,
+    /// Wait as long as a `Retry-After` header asks, as long as it is within `max_backoff_ms`.
+    pub respect_retry_after: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_backoff_ms: 500,
+            max_backoff_ms: 10_000,
+            jitter: 0.2,
+            retryable_status_codes: vec![408, 425, 429, 500, 502, 503, 504],
+            respect_retry_after: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Backoff before attempt `attempt + 1`, given that `attempt` (1-based) just failed.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        let delay = self
+            .base_backoff_ms
+            .saturating_mul(1 << exponent)
+            .min(self.max_backoff_ms);
+
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let factor = 1.0 - jitter * rand::thread_rng().gen::<f64>();
+        Duration::from_millis((delay as f64 * factor) as u64)
+    }
+
+    pub fn is_retryable_status(&self, status: StatusCode) -> bool {
+        self.retryable_status_codes.contains(&status.as_u16())
+    }
+
+    pub fn is_retryable_error(error: &reqwest::Error) -> bool {
+        error.is_timeout() || error.is_connect()
+    }
+
+    /// The delay before retrying `response`, or `None` if it should not be retried.
+    fn retry_delay(&self, response: &Response, attempt: u32) -> Option<Duration> {
+        let backoff = self.backoff(attempt);
+
+        if !self.respect_retry_after {
+            return Some(backoff);
+        }
+
+        match response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| parse_retry_after(value, Utc::now()))
+        {
+            Some(retry_after) if retry_after > Duration::from_millis(self.max_backoff_ms) => None,
+            Some(retry_after) => Some(retry_after.max(backoff)),
+            None => Some(backoff),
+        }
+    }
+}
+
+/// Parses a `Retry-After` value given either as delay-seconds or as an HTTP date.
+pub fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value)
+        .ok()?
+        .with_timezone(&Utc);
+    Some((date - now).to_std().unwrap_or(Duration::ZERO))
+}
+
+/// Sends the request built by `request` until it succeeds, fails permanently or runs out
+/// of attempts. Returns the final outcome together with the number of attempts made.
+pub async fn send_with_retry<F>(
+    policy: &RetryPolicy,
+    request: F,
+) -> (Result<Response, reqwest::Error>, u32)
+where
+    F: Fn() -> RequestBuilder,
+{
+    let max_attempts = policy.max_attempts.max(1);
+    let mut attempt = 1;
+
+    loop {
+        let result = request().send().await;
+
+        let delay = match &result {
+            _ if attempt >= max_attempts => None,
+            Ok(response) if policy.is_retryable_status(response.status()) => {
+                policy.retry_delay(response, attempt)
+            }
+            Err(error) if RetryPolicy::is_retryable_error(error) => Some(policy.backoff(attempt)),
+            _ => None,
+        };
+
+        let Some(delay) = delay else {
+            return (result, attempt);
+        };
+
+        match &result {
+            Ok(response) => log::warn!(
+                "{} answered {}, retrying in {:?} (attempt {}/{})",
+                response.url(),
+                response.status(),
+                delay,
+                attempt,
+                max_attempts
+            ),
+            Err(error) => log::warn!(
+                "{}, retrying in {:?} (attempt {}/{})",
+                error,
+                delay,
+                attempt,
+                max_attempts
+            ),
+        }
+
+        sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use chrono::TimeZone;
+    use reqwest::Client;
+
+    use super::*;
+    use crate::test_support::{serve, TestResponse};
+
+    fn fast_policy() -> RetryPolicy {
+        RetryPolicy {
+            base_backoff_ms: 1,
+            max_backoff_ms: 50,
+            ..RetryPolicy::default()
+        }
+    }
+
+    #[test]
+    fn backs_off_exponentially_up_to_the_maximum() {
+        let policy = RetryPolicy {
+            base_backoff_ms: 100,
+            max_backoff_ms: 1_000,
+            jitter: 0.0,
+            ..RetryPolicy::default()
+        };
+
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(4), Duration::from_millis(800));
+        assert_eq!(policy.backoff(5), Duration::from_millis(1_000));
+        assert_eq!(policy.backoff(40), Duration::from_millis(1_000));
+
+        let jittered = RetryPolicy {
+            jitter: 0.5,
+            ..policy
+        };
+        let delay = jittered.backoff(2);
+        assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn parses_retry_after() {
+        let now = Utc.with_ymd_and_hms(2015, 10, 21, 7, 28, 0).unwrap();
+
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:30 GMT", now),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:27:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[tokio::test]
+    async fn retries_retryable_statuses() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let base = serve(move |_| {
+            if counter.fetch_add(1, Ordering::SeqCst) < 2 {
+                TestResponse::status(503).with_header("Retry-After", "0")
+            } else {
+                TestResponse::ok("done")
+            }
+        })
+        .await;
+
+        let client = Client::new();
+        let (result, attempts) = send_with_retry(&fast_policy(), || client.get(&base)).await;
+
+        assert_eq!(result.unwrap().status(), StatusCode::OK);
+        assert_eq!(attempts, 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_permanent_failures_and_exhausted_attempts() {
+        let not_found = serve(|_| TestResponse::status(404)).await;
+        let unavailable = serve(|_| TestResponse::status(503)).await;
+        let too_long =
+            serve(|_| TestResponse::status(429).with_header("Retry-After", "3600")).await;
+        let client = Client::new();
+        let policy = fast_policy();
+
+        let (result, attempts) = send_with_retry(&policy, || client.get(&not_found)).await;
+        assert_eq!(result.unwrap().status(), StatusCode::NOT_FOUND);
+        assert_eq!(attempts, 1);
+
+        let (result, attempts) = send_with_retry(&policy, || client.get(&unavailable)).await;
+        assert_eq!(result.unwrap().status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(attempts, 3);
+
+        let (result, attempts) = send_with_retry(&policy, || client.get(&too_long)).await;
+        assert_eq!(result.unwrap().status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(attempts, 1);
+    }
+}
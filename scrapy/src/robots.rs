@@ -0,0 +1,357 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use reqwest::Client;
+use tokio::sync::{Mutex, OnceCell};
+use url::Url;
+
+use crate::{error::AppError, http_cache::CachedResponse, warc::Warc};
+
+#[derive(Debug, Clone)]
+struct Rule {
+    allow: bool,
+    pattern: String,
+}
+
+#[derive(Debug, Default)]
+struct Group {
+    agents: Vec<String>,
+    rules: Vec<Rule>,
+    crawl_delay: Option<Duration>,
+}
+
+/// The robots.txt rules that apply to one user-agent on one host.
+#[derive(Debug, Default)]
+pub struct RobotsRules {
+    rules: Vec<Rule>,
+    crawl_delay: Option<Duration>,
+}
+
+impl RobotsRules {
+    pub fn allow_all() -> Self {
+        Self::default()
+    }
+
+    pub fn disallow_all() -> Self {
+        Self {
+            rules: vec![Rule {
+                allow: false,
+                pattern: "/".to_string(),
+            }],
+            crawl_delay: None,
+        }
+    }
+
+    /// Parses a robots.txt body, keeping the group that best matches `user_agent`:
+    /// the longest user-agent line matching its product token, or `*` otherwise.
+    pub fn parse(body: &str, user_agent: &str) -> Self {
+        let mut groups: Vec<Group> = Vec::new();
+        let mut reading_agents = false;
+
+        for line in body.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+
+            match key.trim().to_lowercase().as_str() {
+                "user-agent" => {
+                    if !reading_agents {
+                        groups.push(Group::default());
+                        reading_agents = true;
+                    }
+                    if let Some(group) = groups.last_mut() {
+                        group.agents.push(value.to_lowercase());
+                    }
+                }
+                key @ ("allow" | "disallow") => {
+                    reading_agents = false;
+                    if let Some(group) = groups.last_mut() {
+                        if !value.is_empty() {
+                            group.rules.push(Rule {
+                                allow: key == "allow",
+                                pattern: value.to_string(),
+                            });
+                        }
+                    }
+                }
+                "crawl-delay" => {
+                    reading_agents = false;
+                    if let Some(group) = groups.last_mut() {
+                        group.crawl_delay = value
+                            .parse::<f64>()
+                            .ok()
+                            .filter(|secs| secs.is_finite() && *secs >= 0.0)
+                            .map(Duration::from_secs_f64);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let token = user_agent
+            .split(['/', ' '])
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+
+        let best_agent = groups
+            .iter()
+            .flat_map(|g| g.agents.iter())
+            .filter(|agent| *agent != "*" && !token.is_empty() && token.starts_with(agent.as_str()))
+            .max_by_key(|agent| agent.len())
+            .cloned()
+            .unwrap_or_else(|| "*".to_string());
+
+        let mut rules = RobotsRules::default();
+        for group in groups.iter().filter(|g| g.agents.contains(&best_agent)) {
+            rules.rules.extend(group.rules.iter().cloned());
+            rules.crawl_delay = rules.crawl_delay.or(group.crawl_delay);
+        }
+        rules
+    }
+
+    /// Applies the longest matching rule; `Allow` wins ties and unmatched paths are allowed.
+    pub fn is_allowed(&self, url: &Url) -> bool {
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+
+        self.rules
+            .iter()
+            .filter(|rule| pattern_matches(&rule.pattern, &path))
+            .max_by_key(|rule| (rule.pattern.len(), rule.allow))
+            .is_none_or(|rule| rule.allow)
+    }
+
+    pub fn crawl_delay(&self) -> Option<Duration> {
+        self.crawl_delay
+    }
+}
+
+/// Matches a robots.txt path pattern, supporting `*` wildcards and a trailing `$` anchor.
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+
+    let mut parts = pattern.split('*');
+    let Some(mut rest) = path.strip_prefix(parts.next().unwrap_or_default()) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    if parts.is_empty() {
+        return !anchored || rest.is_empty();
+    }
+
+    for (i, part) in parts.iter().enumerate() {
+        if anchored && i == parts.len() - 1 {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
+        }
+    }
+
+    true
+}
+
+/// Fetches robots.txt once per origin and answers allow/crawl-delay questions from the cache.
+pub struct RobotsCache {
+    http_client: Client,
+    user_agent: String,
+    rules: Mutex<HashMap<String, Arc<OnceCell<Arc<RobotsRules>>>>>,
+    warc: Option<Arc<Warc>>,
+}
+
+impl RobotsCache {
+    pub fn new(http_client: Client, user_agent: &str) -> Self {
+        Self {
+            http_client,
+            user_agent: user_agent.to_string(),
+            rules: Mutex::new(HashMap::new()),
+            warc: None,
+        }
+    }
+
+    /// Records robots.txt responses to `warc`, or reads them from it when replaying.
+    pub fn with_warc(mut self, warc: Arc<Warc>) -> Self {
+        self.warc = Some(warc);
+        self
+    }
+
+    pub async fn is_allowed(&self, url: &Url) -> bool {
+        self.rules_for(url).await.is_allowed(url)
+    }
+
+    pub async fn crawl_delay(&self, url: &Url) -> Option<Duration> {
+        self.rules_for(url).await.crawl_delay()
+    }
+
+    async fn rules_for(&self, url: &Url) -> Arc<RobotsRules> {
+        let origin = url.origin().ascii_serialization();
+        let cell = self
+            .rules
+            .lock()
+            .await
+            .entry(origin.clone())
+            .or_default()
+            .clone();
+
+        cell.get_or_init(|| async {
+            let rules = self.fetch(url).await;
+            log::debug!("robots.txt rules for {}: {:?}", origin, rules);
+            Arc::new(rules)
+        })
+        .await
+        .clone()
+    }
+
+    /// Follows RFC 9309: a missing robots.txt (4xx) allows everything, while a server
+    /// error or an unreachable host disallows everything.
+    async fn fetch(&self, url: &Url) -> RobotsRules {
+        let Ok(robots_url) = url.join("/robots.txt") else {
+            return RobotsRules::allow_all();
+        };
+
+        // A replayed crawl only has a robots.txt if the recorded one could fetch it.
+        let response = match &self.warc {
+            Some(warc) if warc.is_replaying() => warc
+                .response(&robots_url)
+                .ok_or_else(|| AppError::NotRecorded(robots_url.to_string())),
+            _ => self.download(&robots_url).await,
+        };
+        let response = match response {
+            Ok(response) => response,
+            Err(e) => {
+                log::warn!("Failed to fetch {}: {}", robots_url, e);
+                return RobotsRules::disallow_all();
+            }
+        };
+
+        match response.status {
+            200..=299 => RobotsRules::parse(&response.body, &self.user_agent),
+            400..=499 => RobotsRules::allow_all(),
+            status => {
+                log::warn!("{} answered with status {}", robots_url, status);
+                RobotsRules::disallow_all()
+            }
+        }
+    }
+
+    async fn download(&self, robots_url: &Url) -> Result<CachedResponse, AppError> {
+        let response = self.http_client.get(robots_url.clone()).send().await?;
+        let status = response.status().as_u16();
+        let headers = response.headers().clone();
+        let body = response.bytes().await?;
+
+        if let Some(warc) = &self.warc {
+            if let Err(e) = warc
+                .record_response(robots_url, status, &headers, &body)
+                .await
+            {
+                log::warn!("Failed to record {}: {}", robots_url, e);
+            }
+        }
+        Ok(CachedResponse::from_bytes(
+            robots_url, status, &headers, &body,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::test_support::{serve, TestResponse};
+
+    const ROBOTS: &str = "
+        # comment
+        User-agent: *
+        Disallow: /private
+        Allow: /private/public
+        Disallow: /*.pdf$
+        Crawl-delay: 2
+
+        User-agent: scrapy
+        User-agent: otherbot
+        Disallow: /admin
+        Crawl-delay: 0.5
+    ";
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    #[test]
+    fn applies_wildcard_group() {
+        let rules = RobotsRules::parse(ROBOTS, "somebot/1.0");
+
+        assert!(rules.is_allowed(&url("https://example.com/")));
+        assert!(!rules.is_allowed(&url("https://example.com/private/page")));
+        assert!(rules.is_allowed(&url("https://example.com/private/public/page")));
+        assert!(!rules.is_allowed(&url("https://example.com/files/a.pdf")));
+        assert!(rules.is_allowed(&url("https://example.com/files/a.pdf?download=1")));
+        assert_eq!(rules.crawl_delay(), Some(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn prefers_specific_user_agent_group() {
+        let rules = RobotsRules::parse(ROBOTS, "Scrapy/0.1 (+https://example.com)");
+
+        assert!(rules.is_allowed(&url("https://example.com/private/page")));
+        assert!(!rules.is_allowed(&url("https://example.com/admin/users")));
+        assert_eq!(rules.crawl_delay(), Some(Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn matches_patterns() {
+        assert!(pattern_matches("/", "/anything"));
+        assert!(pattern_matches("/a*c", "/abbbc/d"));
+        assert!(pattern_matches("/a*c$", "/abbbc"));
+        assert!(!pattern_matches("/a*c$", "/abbbc/d"));
+        assert!(pattern_matches("/exact$", "/exact"));
+        assert!(!pattern_matches("/exact$", "/exactly"));
+        assert!(!pattern_matches("/b", "/a/b"));
+    }
+
+    #[tokio::test]
+    async fn fetches_and_caches_per_origin() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let base = serve(move |request| {
+            assert_eq!(request.path, "/robots.txt");
+            assert_eq!(request.headers.get("user-agent").unwrap(), "scrapy/1.0");
+            counter.fetch_add(1, Ordering::SeqCst);
+            TestResponse::ok("User-agent: *\nDisallow: /blocked\nCrawl-delay: 1")
+                .with_header("Content-Type", "text/plain")
+        })
+        .await;
+
+        let client = Client::builder().user_agent("scrapy/1.0").build().unwrap();
+        let cache = RobotsCache::new(client, "scrapy/1.0");
+
+        assert!(cache.is_allowed(&url(&format!("{}/open", base))).await);
+        assert!(!cache.is_allowed(&url(&format!("{}/blocked/1", base))).await);
+        assert_eq!(
+            cache.crawl_delay(&url(&format!("{}/", base))).await,
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn handles_missing_and_failing_robots() {
+        let missing = serve(|_| TestResponse::status(404)).await;
+        let failing = serve(|_| TestResponse::status(503)).await;
+        let cache = RobotsCache::new(Client::new(), "scrapy");
+
+        assert!(cache.is_allowed(&url(&format!("{}/page", missing))).await);
+        assert!(!cache.is_allowed(&url(&format!("{}/page", failing))).await);
+    }
+}
@@ -0,0 +1,46 @@
+use std::sync::Arc;
+
+use rocket::serde::json::Json;
+use rocket::{get, State};
+
+use crate::error::AppError;
+use crate::models::{StoreQuery, StoredCrawl, StoredPage, StoredRecord};
+use crate::store::Store;
+
+#[get("/crawls?<query..>")]
+pub async fn list_crawls(
+    query: StoreQuery,
+    store: &State<Arc<Store>>,
+) -> Result<Json<Vec<StoredCrawl>>, rocket::http::Status> {
+    respond(store.call(move |store| store.crawls(&query)).await)
+}
+
+#[get("/pages?<query..>")]
+pub async fn list_pages(
+    query: StoreQuery,
+    store: &State<Arc<Store>>,
+) -> Result<Json<Vec<StoredPage>>, rocket::http::Status> {
+    respond(store.call(move |store| store.pages(&query)).await)
+}
+
+#[get("/records?<query..>")]
+pub async fn list_records(
+    query: StoreQuery,
+    store: &State<Arc<Store>>,
+) -> Result<Json<Vec<StoredRecord>>, rocket::http::Status> {
+    respond(store.call(move |store| store.records(&query)).await)
+}
+
+fn respond<T>(result: Result<T, AppError>) -> Result<Json<T>, rocket::http::Status> {
+    match result {
+        Ok(rows) => Ok(Json(rows)),
+        Err(e @ AppError::InvalidParams(_)) => {
+            log::warn!("Rejected store query: {}", e);
+            Err(rocket::http::Status::BadRequest)
+        }
+        Err(e) => {
+            log::error!("Failed to read the crawl store: {}", e);
+            Err(rocket::http::Status::InternalServerError)
+        }
+    }
+}
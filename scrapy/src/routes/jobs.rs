@@ -0,0 +1,57 @@
+use rocket::response::status::Accepted;
+use rocket::serde::json::Json;
+use rocket::{delete, get, post, State};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::export::{Export, ExportFormat};
+use crate::models::{CrawlJobInfo, ScrapeParams};
+use crate::services::JobService;
+
+#[post("/crawls", data = "<params>")]
+pub async fn create_crawl(
+    params: Json<ScrapeParams>,
+    job_service: &State<Arc<JobService>>,
+) -> Result<Accepted<Json<Value>>, rocket::http::Status> {
+    log::info!("Starting crawl job for URL: {}", params.url);
+
+    match job_service.start(params.into_inner()).await {
+        Ok(id) => Ok(Accepted(Json(json!({ "id": id })))),
+        Err(e @ (AppError::InvalidParams(_) | AppError::UnknownModel(_))) => {
+            log::warn!("Rejected crawl job: {}", e);
+            Err(rocket::http::Status::BadRequest)
+        }
+        Err(e) => {
+            log::error!("Failed to start crawl job: {}", e);
+            Err(rocket::http::Status::InternalServerError)
+        }
+    }
+}
+
+#[get("/crawls/<id>")]
+pub async fn get_crawl(
+    id: Uuid,
+    job_service: &State<Arc<JobService>>,
+) -> Option<Json<CrawlJobInfo>> {
+    job_service.get(id).await.map(Json)
+}
+
+/// The job's results so far, in the `ExportFormat` named by `?format=` or `Accept`.
+#[get("/crawls/<id>/results")]
+pub async fn get_crawl_results(
+    id: Uuid,
+    format: ExportFormat,
+    job_service: &State<Arc<JobService>>,
+) -> Option<Export> {
+    job_service.export(id, format).await
+}
+
+#[delete("/crawls/<id>")]
+pub async fn cancel_crawl(
+    id: Uuid,
+    job_service: &State<Arc<JobService>>,
+) -> Option<Json<CrawlJobInfo>> {
+    job_service.cancel(id).await.map(Json)
+}
@@ -2,15 +2,21 @@ use rocket::{get, serde::json::Json};
 use rocket::{post, State};
 use std::sync::Arc;
 
-use crate::models::{ScrapeParams, ScrapingResult};
-use crate::services::CrawlerService;
-use crate::utils::get_all_models;
+use crate::error::AppError;
+use crate::export::{Export, ExportFormat};
+use crate::models::ScrapeParams;
+use crate::pricing::{self, PricingTable};
+use crate::services::{AIService, CrawlerService};
 
-pub use ws::websocket;
 pub use events::sse_events;
+pub use history::{list_crawls, list_pages, list_records};
+pub use jobs::{cancel_crawl, create_crawl, get_crawl, get_crawl_results};
+pub use ws::websocket;
 
-mod ws;
 mod events;
+mod history;
+mod jobs;
+mod ws;
 
 #[get("/")]
 pub fn index() -> &'static str {
@@ -19,15 +25,37 @@ pub fn index() -> &'static str {
 }
 
 #[get("/models")]
-pub fn get_models() -> Json<Vec<String>> {
-    Json(get_all_models())
+pub async fn get_models(ai_service: &State<Arc<AIService>>) -> Json<Vec<String>> {
+    Json(ai_service.list_models().await)
 }
 
+#[get("/pricing")]
+pub fn get_pricing() -> Json<PricingTable> {
+    Json(pricing::table())
+}
+
+/// Rereads the pricing config file, keeping the current prices if it is invalid.
+#[post("/pricing/reload")]
+pub fn reload_pricing() -> Result<Json<PricingTable>, rocket::http::Status> {
+    match pricing::reload() {
+        Ok(table) => {
+            log::info!("Reloaded pricing for {} models", table.len());
+            Ok(Json(table))
+        }
+        Err(e) => {
+            log::error!("Failed to reload pricing: {}", e);
+            Err(rocket::http::Status::UnprocessableEntity)
+        }
+    }
+}
+
+/// Returns the results as JSON, or in the `ExportFormat` named by `?format=` or `Accept`.
 #[post("/crawl", data = "<params>")]
 pub async fn crawl(
     params: Json<ScrapeParams>,
+    format: ExportFormat,
     crawler_service: &State<Arc<CrawlerService>>,
-) -> Result<Json<Vec<ScrapingResult>>, rocket::http::Status> {
+) -> Result<Export, rocket::http::Status> {
     log::info!(
         "Initiating crawl request for URL: {} with parameters: {:#?}",
         params.url,
@@ -36,23 +64,17 @@ pub async fn crawl(
 
     let params = params.into_inner();
     match crawler_service.crawl(params.clone()).await {
-        Ok(results) => {
+        Ok(report) => {
             log::info!(
                 "Crawl operation completed successfully for URL: {}",
                 params.url
             );
-            log::debug!("Crawl results: {:?}", results);
-            let response = results
-                .into_iter()
-                .map(|r| ScrapingResult {
-                    all_data: r.data.as_array().unwrap_or(&Vec::new()).to_vec(),
-                    input_tokens: r.usage_metadata.input_tokens,
-                    output_tokens: r.usage_metadata.output_tokens,
-                    total_cost: r.usage_metadata.total_cost,
-                    pagination_info: None,
-                })
-                .collect();
-            Ok(Json(response))
+            log::debug!("Crawl results: {:?}", report);
+            Ok(Export::new(format, &params, report.into_scraping_results()))
+        }
+        Err(e @ (AppError::InvalidParams(_) | AppError::UnknownModel(_))) => {
+            log::warn!("Rejected crawl request: {}", e);
+            Err(rocket::http::Status::BadRequest)
         }
         Err(e) => {
             log::error!("Crawl operation failed: {}", e);
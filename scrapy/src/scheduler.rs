@@ -0,0 +1,202 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{
+    sync::{Mutex as AsyncMutex, OwnedSemaphorePermit, Semaphore},
+    time::{sleep_until, Instant},
+};
+use url::Url;
+
+struct HostSlot {
+    in_flight: Arc<Semaphore>,
+    next_start: AsyncMutex<Instant>,
+}
+
+/// Grants permission to fetch a URL while keeping per-host politeness: at most
+/// `max_in_flight` requests per host and at least `min_interval` between their starts.
+/// Requests to different hosts only share the `global` limit, which may also be shared
+/// with other schedulers.
+pub struct HostScheduler {
+    min_interval: Duration,
+    max_in_flight: usize,
+    global: Arc<Semaphore>,
+    hosts: Mutex<HashMap<String, Arc<HostSlot>>>,
+}
+
+/// Held for the duration of a request; dropping it frees the host and global slots.
+pub struct FetchPermit {
+    _host: OwnedSemaphorePermit,
+    _global: OwnedSemaphorePermit,
+}
+
+impl HostScheduler {
+    pub fn new(min_interval: Duration, max_in_flight: usize, global: Arc<Semaphore>) -> Self {
+        Self {
+            min_interval,
+            max_in_flight: max_in_flight.max(1),
+            global,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Waits until `url` may be fetched. `interval` replaces the default minimum
+    /// interval for this request, e.g. with a robots.txt crawl-delay.
+    ///
+    /// The global slot is only taken once the host's interval has passed, so requests
+    /// waiting on a slow host never keep other hosts from being fetched.
+    pub async fn acquire(&self, url: &str, interval: Option<Duration>) -> FetchPermit {
+        let slot = self.slot(url);
+        let interval = interval.unwrap_or(self.min_interval);
+
+        let host = slot
+            .in_flight
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("host semaphore is never closed");
+
+        let start = {
+            let mut next_start = slot.next_start.lock().await;
+            let start = (*next_start).max(Instant::now());
+            *next_start = start + interval;
+            start
+        };
+        sleep_until(start).await;
+
+        let global = self
+            .global
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("global semaphore is never closed");
+        // Waiting for the global slot may have delayed the start past the reserved one.
+        {
+            let mut next_start = slot.next_start.lock().await;
+            *next_start = (*next_start).max(Instant::now() + interval);
+        }
+
+        FetchPermit {
+            _host: host,
+            _global: global,
+        }
+    }
+
+    fn slot(&self, url: &str) -> Arc<HostSlot> {
+        let key = Url::parse(url)
+            .ok()
+            .and_then(|url| {
+                let host = url.host_str()?.to_string();
+                Some(match url.port_or_known_default() {
+                    Some(port) => format!("{}:{}", host, port),
+                    None => host,
+                })
+            })
+            .unwrap_or_else(|| url.to_string());
+
+        self.hosts
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_insert_with(|| {
+                Arc::new(HostSlot {
+                    in_flight: Arc::new(Semaphore::new(self.max_in_flight)),
+                    next_start: AsyncMutex::new(Instant::now()),
+                })
+            })
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn spaces_requests_to_the_same_host() {
+        let scheduler =
+            HostScheduler::new(Duration::from_millis(50), 4, Arc::new(Semaphore::new(4)));
+        let started = Instant::now();
+
+        for _ in 0..3 {
+            let _permit = scheduler.acquire("https://a.example/page", None).await;
+        }
+
+        assert!(started.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn lets_different_hosts_proceed_in_parallel() {
+        let scheduler =
+            HostScheduler::new(Duration::from_millis(200), 1, Arc::new(Semaphore::new(4)));
+        let started = Instant::now();
+
+        let _a = scheduler.acquire("https://a.example/", None).await;
+        let _b = scheduler.acquire("https://b.example/", None).await;
+        let _c = scheduler.acquire("https://c.example/", None).await;
+
+        assert!(started.elapsed() < Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn waiting_on_a_host_leaves_global_slots_to_other_hosts() {
+        let scheduler = Arc::new(HostScheduler::new(
+            Duration::from_secs(10),
+            4,
+            Arc::new(Semaphore::new(2)),
+        ));
+        let _first = scheduler.acquire("https://a.example/1", None).await;
+        let second = {
+            let scheduler = scheduler.clone();
+            tokio::spawn(async move {
+                let _second = scheduler.acquire("https://a.example/2", None).await;
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let started = Instant::now();
+        let _other = scheduler.acquire("https://b.example/", None).await;
+        assert!(started.elapsed() < Duration::from_millis(200));
+        assert!(!second.is_finished());
+        second.abort();
+    }
+
+    #[tokio::test]
+    async fn limits_in_flight_requests_per_host() {
+        let scheduler = Arc::new(HostScheduler::new(
+            Duration::ZERO,
+            1,
+            Arc::new(Semaphore::new(4)),
+        ));
+        let first = scheduler.acquire("https://a.example/1", None).await;
+
+        let waiting = {
+            let scheduler = scheduler.clone();
+            tokio::spawn(async move {
+                let _second = scheduler.acquire("https://a.example/2", None).await;
+            })
+        };
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+
+        drop(first);
+        waiting.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn interval_override_replaces_default() {
+        let scheduler = HostScheduler::new(Duration::from_secs(10), 1, Arc::new(Semaphore::new(1)));
+        let started = Instant::now();
+
+        for _ in 0..2 {
+            let _permit = scheduler
+                .acquire("https://a.example/", Some(Duration::from_millis(20)))
+                .await;
+        }
+
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,294 @@
+//! The subset of JSON Schema used to describe extracted records: `type`, `properties`,
+//! `required`, `items`, `enum`, `additionalProperties` and `description`.
+
+use serde_json::{json, Map, Value};
+
+use crate::models::RecordValidation;
+
+const TYPES: [&str; 7] = [
+    "object", "array", "string", "number", "integer", "boolean", "null",
+];
+
+/// Checks that `schema` describes an object record using only supported keywords' shapes.
+pub fn check_schema(schema: &Value) -> Result<(), String> {
+    let Some(object) = schema.as_object() else {
+        return Err("schema must be an object".to_string());
+    };
+    if types_of(object).is_none_or(|types| types != ["object"]) {
+        return Err("schema must describe an object (\"type\": \"object\")".to_string());
+    }
+    check_node(object, "")
+}
+
+fn check_node(schema: &Map<String, Value>, path: &str) -> Result<(), String> {
+    match schema.get("type") {
+        None => {}
+        Some(Value::String(t)) if TYPES.contains(&t.as_str()) => {}
+        Some(Value::Array(types))
+            if types
+                .iter()
+                .all(|t| t.as_str().is_some_and(|t| TYPES.contains(&t))) => {}
+        Some(other) => return Err(format!("{}: unsupported type {}", display(path), other)),
+    }
+
+    if let Some(properties) = schema.get("properties") {
+        let Some(properties) = properties.as_object() else {
+            return Err(format!("{}: properties must be an object", display(path)));
+        };
+        for (name, property) in properties {
+            let path = format!("{}/{}", path, name);
+            let Some(property) = property.as_object() else {
+                return Err(format!("{}: schema must be an object", path));
+            };
+            check_node(property, &path)?;
+        }
+    }
+
+    if let Some(required) = schema.get("required") {
+        if !required
+            .as_array()
+            .is_some_and(|names| names.iter().all(Value::is_string))
+        {
+            return Err(format!(
+                "{}: required must be a list of names",
+                display(path)
+            ));
+        }
+    }
+
+    if let Some(items) = schema.get("items") {
+        let path = format!("{}/items", path);
+        let Some(items) = items.as_object() else {
+            return Err(format!("{}: schema must be an object", path));
+        };
+        check_node(items, &path)?;
+    }
+
+    Ok(())
+}
+
+/// The response shape requested from providers: an object whose `records` follow `record`.
+/// Some structured-output APIs only accept an object at the root.
+pub fn records_schema(record: &Value) -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "records": { "type": "array", "items": record },
+        },
+        "required": ["records"],
+    })
+}
+
+/// Pulls the records out of a provider response, accepting a bare array or a single
+/// record as well as the requested `{"records": [...]}`.
+pub fn into_records(response: Value) -> Vec<Value> {
+    match response {
+        Value::Object(mut object) if object.get("records").is_some_and(Value::is_array) => {
+            match object.remove("records") {
+                Some(Value::Array(records)) => records,
+                _ => vec![],
+            }
+        }
+        Value::Array(records) => records,
+        Value::Null => vec![],
+        record => vec![record],
+    }
+}
+
+/// Validates every record, listing only the ones that do not match `schema`.
+pub fn validate_records(schema: &Value, records: &[Value]) -> Vec<RecordValidation> {
+    records
+        .iter()
+        .enumerate()
+        .map(|(index, record)| RecordValidation {
+            index,
+            errors: validate(schema, record),
+        })
+        .filter(|validation| !validation.errors.is_empty())
+        .collect()
+}
+
+/// Validates `value` against `schema`, returning one message per violation.
+pub fn validate(schema: &Value, value: &Value) -> Vec<String> {
+    let mut errors = Vec::new();
+    if let Some(schema) = schema.as_object() {
+        validate_node(schema, value, "", &mut errors);
+    }
+    errors
+}
+
+fn validate_node(schema: &Map<String, Value>, value: &Value, path: &str, errors: &mut Vec<String>) {
+    if let Some(types) = types_of(schema) {
+        if !types.iter().any(|t| has_type(value, t)) {
+            errors.push(format!(
+                "{}: expected {}, got {}",
+                display(path),
+                types.join(" or "),
+                type_name(value)
+            ));
+            return;
+        }
+    }
+
+    if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+        if !allowed.contains(value) {
+            errors.push(format!(
+                "{}: {} is not one of {}",
+                display(path),
+                value,
+                Value::from(allowed.as_slice())
+            ));
+        }
+    }
+
+    if let Value::Object(object) = value {
+        let properties = schema.get("properties").and_then(Value::as_object);
+
+        for name in schema
+            .get("required")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+        {
+            if object.get(name).is_none_or(Value::is_null) {
+                errors.push(format!("{}/{}: missing required field", path, name));
+            }
+        }
+
+        for (name, field) in object {
+            match properties
+                .and_then(|p| p.get(name))
+                .and_then(Value::as_object)
+            {
+                Some(field_schema) => {
+                    validate_node(field_schema, field, &format!("{}/{}", path, name), errors)
+                }
+                None if schema.get("additionalProperties") == Some(&Value::Bool(false)) => {
+                    errors.push(format!("{}/{}: unexpected field", path, name));
+                }
+                None => {}
+            }
+        }
+    }
+
+    if let (Value::Array(items), Some(item_schema)) =
+        (value, schema.get("items").and_then(Value::as_object))
+    {
+        for (i, item) in items.iter().enumerate() {
+            validate_node(item_schema, item, &format!("{}/{}", path, i), errors);
+        }
+    }
+}
+
+fn types_of(schema: &Map<String, Value>) -> Option<Vec<&str>> {
+    match schema.get("type")? {
+        Value::String(t) => Some(vec![t.as_str()]),
+        Value::Array(types) => Some(types.iter().filter_map(Value::as_str).collect()),
+        _ => None,
+    }
+}
+
+fn has_type(value: &Value, t: &str) -> bool {
+    match t {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => {
+            value.is_i64() || value.is_u64() || value.as_f64().is_some_and(|n| n.fract() == 0.0)
+        }
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        _ => false,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn display(path: &str) -> &str {
+    if path.is_empty() {
+        "/"
+    } else {
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn product_schema() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "name": { "type": "string", "description": "Product name" },
+                "price": { "type": "number" },
+                "stock": { "type": ["integer", "null"] },
+                "condition": { "enum": ["new", "used"] },
+                "tags": { "type": "array", "items": { "type": "string" } },
+            },
+            "required": ["name", "price"],
+        })
+    }
+
+    #[test]
+    fn checks_schemas() {
+        assert!(check_schema(&product_schema()).is_ok());
+        assert!(check_schema(&json!(["name"])).is_err());
+        assert!(check_schema(&json!({ "type": "array" })).is_err());
+        assert!(check_schema(&json!({
+            "type": "object",
+            "properties": { "price": { "type": "money" } },
+        }))
+        .is_err());
+    }
+
+    #[test]
+    fn validates_records() {
+        let schema = product_schema();
+
+        let valid = json!({
+            "name": "Lamp",
+            "price": 19.5,
+            "stock": null,
+            "condition": "new",
+            "tags": ["home"],
+            "extra": true,
+        });
+        assert!(validate(&schema, &valid).is_empty());
+
+        let invalid =
+            json!({ "price": "19.50", "stock": 1.5, "condition": "broken", "tags": ["a", 1] });
+        assert_eq!(
+            validate(&schema, &invalid),
+            vec![
+                "/name: missing required field",
+                r#"/condition: "broken" is not one of ["new","used"]"#,
+                "/price: expected number, got string",
+                "/stock: expected integer or null, got number",
+                "/tags/1: expected string, got number",
+            ]
+        );
+    }
+
+    #[test]
+    fn unwraps_records() {
+        assert_eq!(
+            into_records(json!({ "records": [{ "a": 1 }] })),
+            vec![json!({ "a": 1 })]
+        );
+        assert_eq!(into_records(json!([{ "a": 1 }])), vec![json!({ "a": 1 })]);
+        assert_eq!(into_records(json!({ "a": 1 })), vec![json!({ "a": 1 })]);
+        assert!(into_records(Value::Null).is_empty());
+    }
+}
@@ -0,0 +1,57 @@
+use std::path::PathBuf;
+
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::error::AppError;
+use crate::utils::{cache_dir, write_atomically};
+
+use super::ai_service::{AiRequest, AiResponse};
+
+/// On-disk store of provider responses, one JSON file per request. Requests are keyed by
+/// everything that shapes the answer: the model, both prompts and the generation config.
+pub struct AiCache {
+    dir: PathBuf,
+}
+
+impl AiCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// The `ai` directory under `utils::cache_dir`.
+    pub fn from_env() -> Self {
+        Self::new(cache_dir().join("ai"))
+    }
+
+    pub fn key(model: &str, request: &AiRequest) -> String {
+        let request = json!({
+            "model": model,
+            "systemPrompt": request.system_prompt,
+            "userPrompt": request.user_prompt,
+            "maxOutputTokens": request.max_output_tokens,
+            "responseSchema": request.response_schema,
+        });
+        format!("{:x}", Sha256::digest(request.to_string().as_bytes()))
+    }
+
+    /// The stored response for `key`; unreadable entries count as missing.
+    pub async fn get(&self, key: &str) -> Option<AiResponse> {
+        let entry = tokio::fs::read(self.path(key)).await.ok()?;
+        match serde_json::from_slice(&entry) {
+            Ok(response) => Some(response),
+            Err(e) => {
+                log::warn!("Ignoring corrupt AI cache entry {}: {}", key, e);
+                None
+            }
+        }
+    }
+
+    pub async fn put(&self, key: &str, response: &AiResponse) -> Result<(), AppError> {
+        write_atomically(&self.path(key), &serde_json::to_vec(response)?).await
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+}
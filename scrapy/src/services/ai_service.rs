@@ -4,182 +4,824 @@ use google_generative_ai_rs::v1::{
     api::Client,
     gemini::{
         request::{GenerationConfig, Request, SystemInstructionContent, SystemInstructionPart},
+        response::GeminiResponse,
         Content, Model, Part, Role,
     },
 };
 use log::{debug, info};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::sync::Arc;
-use tokio::sync::Mutex;
 
-use crate::models::{ScrapeParams, UsageMetadata};
-use crate::utils::calculate_price;
+use crate::budget::Budget;
+use crate::chunk::estimate_tokens;
+use crate::models::{AiCacheMode, ScrapeParams, UsageMetadata};
+use crate::pricing;
+use crate::schema::{into_records, records_schema, validate_records};
+use crate::utils::{get_all_models, price_of};
+use crate::warc::Warc;
 use crate::{error::AppError, models::AiScrapingResult};
 
+use super::{AiCache, OllamaProvider, OpenAIProvider};
+
+const DEFAULT_PROVIDER: &str = "gemini";
+
+/// The shortest answer worth asking for when the budget cannot cover a full-length one.
+const MIN_OUTPUT_TOKENS: u64 = 1024;
+
+/// A provider-neutral extraction request. Providers translate it to their own API and
+/// are expected to ask for a JSON response.
+#[derive(Debug, Clone)]
+pub struct AiRequest {
+    /// Model name without the provider prefix.
+    pub model: String,
+    /// Key of the crawl that sent the request; providers fall back to their own when empty.
+    pub api_key: String,
+    pub system_prompt: String,
+    pub user_prompt: String,
+    pub max_output_tokens: u32,
+    /// JSON Schema the response must follow, for providers that support structured output.
+    pub response_schema: Option<Value>,
+}
+
+/// A provider's answer to one `AiRequest`, with the tokens that request used. Cost is
+/// worked out by `AIService` from the model's pricing.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AiResponse {
+    pub text: String,
+    pub input_tokens: u64,
+    /// Input tokens the provider served from its prompt cache, included in `input_tokens`.
+    pub cached_input_tokens: u64,
+    pub output_tokens: u64,
+}
+
 #[async_trait]
 pub trait AIProvider: Send + Sync {
-    async fn process_request(&self, request: Request) -> Result<String, AppError>;
-    fn build_request(&self, system_prompt: String, user_prompt: String) -> Request;
-    async fn build_client(&self, model: &str, api_key: &str) -> Result<(), AppError>;
-    async fn get_usage_metadata(&self) -> UsageMetadata;
+    /// Prefix that selects this provider in `ScrapeParams::model`, e.g. `openai` in
+    /// `openai:gpt-4o-mini`.
+    fn name(&self) -> &'static str;
+    /// Models this provider serves, without the provider prefix.
+    async fn list_models(&self) -> Vec<String>;
+    async fn process_request(&self, request: AiRequest) -> Result<AiResponse, AppError>;
+    fn build_request(
+        &self,
+        model: &str,
+        api_key: &str,
+        system_prompt: String,
+        user_prompt: String,
+    ) -> AiRequest {
+        AiRequest {
+            model: model.to_string(),
+            api_key: api_key.to_string(),
+            system_prompt,
+            user_prompt,
+            max_output_tokens: 8192,
+            response_schema: None,
+        }
+    }
+    /// Rejects models the provider cannot serve before a crawl starts.
+    fn check_model(&self, _model: &str) -> Result<(), AppError> {
+        Ok(())
+    }
 }
 
-pub struct GeminiAIProvider {
-    model: Mutex<Option<String>>,
-    client: Mutex<Option<Client>>,
-    usage_metadata: Mutex<UsageMetadata>,
-}
+/// Builds a client for every request, from that request's model and key.
+pub struct GeminiAIProvider;
 
 impl GeminiAIProvider {
     pub fn new() -> Self {
-        Self {
-            model: Mutex::new(None),
-            client: Mutex::new(None),
-            usage_metadata: UsageMetadata {
-                input_tokens: 0,
-                output_tokens: 0,
-                total_cost: 0.0,
-            }
-            .into(),
-        }
+        Self
     }
 }
 
 #[async_trait]
 impl AIProvider for GeminiAIProvider {
-    async fn build_client(&self, model: &str, api_key: &str) -> Result<(), AppError> {
-        *self.client.lock().await = Some(Client::new_from_model(
-            Model::Gemini1_5Flash,
-            api_key.to_string(),
-        ));
-        *self.model.lock().await = Some(model.to_string());
-        Ok(())
+    fn name(&self) -> &'static str {
+        "gemini"
     }
 
-    async fn process_request(&self, request: Request) -> Result<String, AppError> {
-        let client = self.client.lock().await;
-        if let Some(client) = client.as_ref() {
-            match client.post(30, &request).await {
-                Ok(response) => {
-                    if let Some(stream_response) = response.rest() {
-                        if let Some(candidate) = stream_response.candidates.first() {
-                            if let Some(part) = candidate.content.parts.first() {
-                                if let Some(text) = part.text.as_ref() {
-                                    return Ok(text.to_string());
-                                }
-                            }
-                        }
-
-                        if let Some(metadata) = stream_response.usage_metadata {
-                            let mut usage_metadata = self.usage_metadata.lock().await;
-
-                            if let Some(model) = &*self.model.lock().await {
-                                *usage_metadata = UsageMetadata {
-                                    input_tokens: metadata.prompt_token_count,
-                                    output_tokens: metadata.candidates_token_count,
-                                    total_cost: calculate_price(
-                                        model,
-                                        metadata.prompt_token_count,
-                                        metadata.candidates_token_count,
-                                    ),
-                                };
-                            }
-                        }
-                    }
-                    Err(AppError::AI("No valid response from AI".to_string()))
-                }
-                Err(e) => Err(AppError::AI(e.to_string())),
-            }
-        } else {
-            Err(AppError::AI("AI client not initialized".to_string()))
-        }
+    async fn list_models(&self) -> Vec<String> {
+        get_all_models()
+            .into_iter()
+            .filter(|model| !model.contains(':'))
+            .collect()
     }
 
-    fn build_request(&self, system_prompt: String, user_prompt: String) -> Request {
-        Request {
-            contents: vec![Content {
-                role: Role::User,
-                parts: vec![Part {
-                    text: Some(user_prompt),
-                    inline_data: None,
-                    file_data: None,
-                    video_metadata: None,
-                }],
-            }],
-            tools: vec![],
-            safety_settings: vec![],
-            generation_config: Some(GenerationConfig {
-                temperature: None,
-                top_p: None,
-                top_k: None,
-                candidate_count: None,
-                max_output_tokens: Some(8192),
-                stop_sequences: None,
-                response_mime_type: Some("application/json".to_string()),
-            }),
-            system_instruction: Some(SystemInstructionContent {
-                parts: vec![SystemInstructionPart {
-                    text: Some(system_prompt),
-                }],
-            }),
-        }
+    fn check_model(&self, model: &str) -> Result<(), AppError> {
+        gemini_model(model).map(|_| ())
     }
 
-    async fn get_usage_metadata(&self) -> UsageMetadata {
-        self.usage_metadata.lock().await.clone()
+    async fn process_request(&self, request: AiRequest) -> Result<AiResponse, AppError> {
+        let client = Client::new_from_model(gemini_model(&request.model)?, request.api_key.clone());
+        let request = gemini_request(request);
+
+        let response = client
+            .post(30, &request)
+            .await
+            .map_err(|e| AppError::AI(e.to_string()))?;
+        response
+            .rest()
+            .and_then(gemini_response)
+            .ok_or_else(|| AppError::AI("No valid response from AI".to_string()))
     }
 }
 
-pub struct AIService<T: AIProvider> {
-    ai_provider: Arc<T>,
+/// The text of the first candidate, with the usage the response reports.
+fn gemini_response(response: GeminiResponse) -> Option<AiResponse> {
+    let text = response
+        .candidates
+        .first()?
+        .content
+        .parts
+        .first()?
+        .text
+        .clone()?;
+    let (input_tokens, output_tokens) = response
+        .usage_metadata
+        .map(|usage| (usage.prompt_token_count, usage.candidates_token_count))
+        .unwrap_or_default();
+
+    Some(AiResponse {
+        text,
+        input_tokens,
+        cached_input_tokens: 0,
+        output_tokens,
+    })
 }
 
-impl<T: AIProvider + 'static> AIService<T> {
-    pub fn new(ai_provider: T) -> Self {
+/// The Gemini model for a priced model name. Names without a dedicated `Model` variant
+/// are passed through as custom model names; models without pricing are rejected so
+/// that the reported cost always matches the model that was called.
+fn gemini_model(model: &str) -> Result<Model, AppError> {
+    if model.contains(':') || pricing::get(model).is_none() {
+        return Err(AppError::UnknownModel(model.to_string()));
+    }
+
+    Ok(match model {
+        "gemini-pro" => Model::GeminiPro,
+        "gemini-1.5-pro-latest" => Model::Gemini1_5Pro,
+        "gemini-1.5-flash-latest" => Model::Gemini1_5Flash,
+        custom => Model::Custom(custom.to_string()),
+    })
+}
+
+/// The Gemini API used here has no response schema setting, so the schema is spelled out
+/// in the system instruction instead.
+fn gemini_request(request: AiRequest) -> Request {
+    let system_prompt = match &request.response_schema {
+        Some(schema) => format!(
+            "{}\n\nThe response must be JSON matching this JSON Schema: {}",
+            request.system_prompt, schema
+        ),
+        None => request.system_prompt,
+    };
+
+    Request {
+        contents: vec![Content {
+            role: Role::User,
+            parts: vec![Part {
+                text: Some(request.user_prompt),
+                inline_data: None,
+                file_data: None,
+                video_metadata: None,
+            }],
+        }],
+        tools: vec![],
+        safety_settings: vec![],
+        generation_config: Some(GenerationConfig {
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            candidate_count: None,
+            max_output_tokens: Some(request.max_output_tokens),
+            stop_sequences: None,
+            response_mime_type: Some("application/json".to_string()),
+        }),
+        system_instruction: Some(SystemInstructionContent {
+            parts: vec![SystemInstructionPart {
+                text: Some(system_prompt),
+            }],
+        }),
+    }
+}
+
+/// Routes extraction requests to the provider named by the model's `provider:` prefix.
+/// Models without a prefix go to Gemini.
+pub struct AIService {
+    providers: Vec<Arc<dyn AIProvider>>,
+    cache: Option<AiCache>,
+}
+
+impl AIService {
+    pub fn new(providers: Vec<Arc<dyn AIProvider>>) -> Self {
         debug!("Initializing AIService");
-        info!("AIService initialized successfully");
+        info!(
+            "AIService initialized with providers: {}",
+            providers
+                .iter()
+                .map(|p| p.name())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
         Self {
-            ai_provider: Arc::new(ai_provider),
+            providers,
+            cache: None,
         }
     }
 
-    pub async fn build_client(&self, model: &str, api_key: &str) -> Result<(), AppError> {
-        self.ai_provider.build_client(model, api_key).await
+    /// Answers repeated requests from `cache`, as allowed by `ScrapeParams::ai_cache`.
+    pub fn with_cache(mut self, cache: AiCache) -> Self {
+        self.cache = Some(cache);
+        self
     }
 
+    /// Gemini, plus the OpenAI-compatible and Ollama providers when their environment
+    /// variables are set, with responses cached under `utils::cache_dir`.
+    pub fn from_env() -> Self {
+        let mut providers: Vec<Arc<dyn AIProvider>> = vec![Arc::new(GeminiAIProvider::new())];
+        if let Some(openai) = OpenAIProvider::from_env() {
+            providers.push(Arc::new(openai));
+        }
+        if let Some(ollama) = OllamaProvider::from_env() {
+            providers.push(Arc::new(ollama));
+        }
+        Self::new(providers).with_cache(AiCache::from_env())
+    }
+
+    /// Every model of every provider, prefixed with the provider name except for Gemini.
+    pub async fn list_models(&self) -> Vec<String> {
+        let mut models = Vec::new();
+        for provider in &self.providers {
+            for model in provider.list_models().await {
+                if provider.name() == DEFAULT_PROVIDER {
+                    models.push(model);
+                } else {
+                    models.push(format!("{}:{}", provider.name(), model));
+                }
+            }
+        }
+        models
+    }
+
+    /// The provider for `model` and the model name with the provider prefix removed.
+    fn provider_for<'a>(
+        &self,
+        model: &'a str,
+    ) -> Result<(&Arc<dyn AIProvider>, &'a str), AppError> {
+        let (name, model_name) = match model.split_once(':') {
+            Some((prefix, rest)) if self.providers.iter().any(|p| p.name() == prefix) => {
+                (prefix, rest)
+            }
+            _ => (DEFAULT_PROVIDER, model),
+        };
+
+        self.providers
+            .iter()
+            .find(|p| p.name() == name)
+            .map(|p| (p, model_name))
+            .ok_or_else(|| AppError::UnknownModel(model.to_string()))
+    }
+
+    /// Fails with `AppError::UnknownModel` if no provider can serve `model`.
+    pub fn check_model(&self, model: &str) -> Result<(), AppError> {
+        let (provider, model) = self.provider_for(model)?;
+        provider.check_model(model)
+    }
+
+    /// Sends the prompts to the provider serving `params.model`. With a `schema`, the
+    /// provider is asked for `{"records": [...]}` and `data` holds the records, each
+    /// validated against `schema`.
+    ///
+    /// A stored response to the same request is reused when the cache allows it. With a
+    /// `warc`, the answer is recorded to it or, when replaying, read from it instead, and
+    /// the cache is not used. Reused answers cost nothing and count as cache hits.
+    /// Otherwise the answer is limited to what is left in `budget`, and the request's
+    /// worst-case usage under that limit is reserved first; it fails with
+    /// `AppError::BudgetExceeded` without being sent if not even a short answer fits.
     pub async fn extract_items(
         &self,
         params: &ScrapeParams,
         system_prompt: &str,
         user_prompt: &str,
+        schema: Option<&Value>,
+        budget: &Budget,
+        warc: Option<&Warc>,
     ) -> Result<AiScrapingResult, AppError> {
         debug!("Extracting items with params: {:?}", params);
 
         let mut result = AiScrapingResult {
+            url: String::new(),
             model: params.model.clone(),
             start_time: Utc::now(),
             end_time: None,
             data: Value::Null,
-            usage_metadata: UsageMetadata {
-                input_tokens: 0,
-                output_tokens: 0,
-                total_cost: 0.0,
-            },
+            usage_metadata: UsageMetadata::default(),
+            validation_errors: vec![],
+            content_size: None,
         };
 
-        self.build_client(&params.model, &params.api_key).await?;
-        let request = self
-            .ai_provider
-            .build_request(system_prompt.to_string(), user_prompt.to_string());
-        let response = self.ai_provider.process_request(request).await?;
+        let (provider, model) = self.provider_for(&params.model)?;
+        let mut request = provider.build_request(
+            model,
+            &params.api_key,
+            system_prompt.to_string(),
+            user_prompt.to_string(),
+        );
+        request.response_schema = schema.map(records_schema);
 
-        if let Ok(value) = serde_json::from_str::<Value>(&response) {
-            result.data = value;
+        let cache = self
+            .cache
+            .as_ref()
+            .filter(|_| params.ai_cache != AiCacheMode::Bypass && warc.is_none());
+        let key = AiCache::key(&params.model, &request);
+        let warc_uri = format!("urn:scrapy:ai:{}", key);
+        let reused = match (warc.filter(|warc| warc.is_replaying()), cache) {
+            (Some(warc), _) => Some(recorded_response(warc, &warc_uri)?),
+            (None, Some(cache)) if params.ai_cache == AiCacheMode::Use => cache.get(&key).await,
+            _ => None,
+        };
+        if let Some(response) = reused {
+            debug!("Answering {} request with an earlier answer", params.model);
+            set_data(&mut result, &response.text, schema);
+            result.usage_metadata = UsageMetadata {
+                cache_hits: 1,
+                saved_cost: response_cost(&params.model, &response),
+                ..UsageMetadata::default()
+            };
+            result.end_time = Some(Utc::now());
+            return Ok(result);
         }
 
-        result.usage_metadata = self.ai_provider.get_usage_metadata().await;
+        let input_tokens = (estimate_tokens(&request.system_prompt)
+            + estimate_tokens(&request.user_prompt)) as u64;
+        let output_tokens = output_allowance(
+            budget,
+            &params.model,
+            input_tokens,
+            u64::from(request.max_output_tokens),
+        );
+        request.max_output_tokens = output_tokens as u32;
+        let reservation = budget.reserve(UsageMetadata {
+            input_tokens,
+            output_tokens,
+            total_cost: price_of(&params.model, input_tokens, 0, output_tokens).unwrap_or_default(),
+            ..UsageMetadata::default()
+        })?;
+
+        let response = match provider.process_request(request).await {
+            Ok(response) => response,
+            Err(e) => {
+                budget.settle(reservation, None);
+                return Err(e);
+            }
+        };
+
+        let parsed = set_data(&mut result, &response.text, schema);
+        result.usage_metadata = UsageMetadata {
+            input_tokens: response.input_tokens,
+            output_tokens: response.output_tokens,
+            total_cost: response_cost(&params.model, &response),
+            cache_misses: u64::from(cache.is_some()),
+            ..UsageMetadata::default()
+        };
+        budget.settle(reservation, Some(&result.usage_metadata));
         result.end_time = Some(Utc::now());
 
+        if let Some(warc) = warc {
+            if let Err(e) = record_response(warc, &warc_uri, &response).await {
+                log::warn!("Failed to record AI response: {}", e);
+            }
+        }
+
+        // Answers that are not JSON are not stored, so that the request is tried again.
+        if let (Some(cache), true) = (cache, parsed) {
+            if let Err(e) = cache.put(&key, &response).await {
+                log::warn!("Failed to cache AI response: {}", e);
+            }
+        }
+
         Ok(result)
     }
 }
+
+/// The longest answer, up to `max_output_tokens`, whose cost still fits in `budget` next
+/// to the prompt. Never less than `MIN_OUTPUT_TOKENS`, so that a request the budget
+/// cannot usefully cover is refused rather than cut short.
+fn output_allowance(
+    budget: &Budget,
+    model: &str,
+    input_tokens: u64,
+    max_output_tokens: u64,
+) -> u64 {
+    let mut allowance = max_output_tokens;
+    if let Some(tokens) = budget.remaining_tokens() {
+        allowance = allowance.min(tokens.saturating_sub(input_tokens));
+    }
+    let output_price = price_of(model, 0, 0, 1_000_000).map(|price| price / 1e6);
+    if let (Some(cost), Some(output_price)) = (budget.remaining_cost(), output_price) {
+        if output_price > 0.0 {
+            let input_cost = price_of(model, input_tokens, 0, 0).unwrap_or_default();
+            allowance = allowance.min(((cost - input_cost).max(0.0) / output_price) as u64);
+        }
+    }
+    allowance.max(MIN_OUTPUT_TOKENS.min(max_output_tokens))
+}
+
+/// Parses the provider's answer into `result.data`, unwrapping and validating the records
+/// when extracting with a schema. Returns whether the answer was JSON.
+fn set_data(result: &mut AiScrapingResult, text: &str, schema: Option<&Value>) -> bool {
+    let parsed = serde_json::from_str::<Value>(text).ok();
+    let is_json = parsed.is_some();
+    result.data = parsed.unwrap_or(Value::Null);
+
+    if let Some(schema) = schema {
+        let records = into_records(result.data.take());
+        result.validation_errors = validate_records(schema, &records);
+        result.data = Value::Array(records);
+    }
+    is_json
+}
+
+/// The answer recorded in `warc` for the request at `uri`.
+fn recorded_response(warc: &Warc, uri: &str) -> Result<AiResponse, AppError> {
+    let recorded = warc
+        .resource(uri)
+        .ok_or_else(|| AppError::NotRecorded(uri.to_string()))?;
+    Ok(serde_json::from_slice(recorded)?)
+}
+
+async fn record_response(warc: &Warc, uri: &str, response: &AiResponse) -> Result<(), AppError> {
+    warc.record_resource(uri, "application/json", &serde_json::to_vec(response)?)
+        .await
+}
+
+/// Models without pricing, such as local Ollama models, cost nothing.
+fn response_cost(model: &str, response: &AiResponse) -> f64 {
+    price_of(
+        model,
+        response.input_tokens,
+        response.cached_input_tokens,
+        response.output_tokens,
+    )
+    .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::calculate_price;
+
+    /// Answers every request with the model it asked for, after a delay and with input
+    /// tokens both equal to the user prompt's length.
+    struct EchoProvider {
+        name: &'static str,
+    }
+
+    fn echo(name: &'static str) -> Arc<dyn AIProvider> {
+        Arc::new(EchoProvider { name })
+    }
+
+    #[async_trait]
+    impl AIProvider for EchoProvider {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        async fn list_models(&self) -> Vec<String> {
+            vec![format!("{}-model", self.name)]
+        }
+
+        async fn process_request(&self, request: AiRequest) -> Result<AiResponse, AppError> {
+            let text = serde_json::json!({
+                "provider": self.name,
+                "model": request.model,
+            })
+            .to_string();
+            let length = request.user_prompt.len() as u64;
+            tokio::time::sleep(std::time::Duration::from_millis(length)).await;
+
+            Ok(AiResponse {
+                text,
+                input_tokens: length,
+                cached_input_tokens: 0,
+                output_tokens: 1,
+            })
+        }
+    }
+
+    async fn extract(service: &AIService, model: &str) -> Result<Value, AppError> {
+        let mut params = ScrapeParams::for_test("https://example.com");
+        params.model = model.to_string();
+        Ok(service
+            .extract_items(&params, "system", "user", None, &Budget::default(), None)
+            .await?
+            .data)
+    }
+
+    #[tokio::test]
+    async fn routes_by_model_prefix() {
+        let service = AIService::new(vec![echo("gemini"), echo("openai"), echo("ollama")]);
+
+        let data = extract(&service, "openai:gpt-4o-mini").await.unwrap();
+        assert_eq!(data["provider"], "openai");
+        assert_eq!(data["model"], "gpt-4o-mini");
+
+        let data = extract(&service, "ollama:llama3.1:8b").await.unwrap();
+        assert_eq!(data["provider"], "ollama");
+        assert_eq!(data["model"], "llama3.1:8b");
+
+        let data = extract(&service, "gemini-1.5-flash-latest").await.unwrap();
+        assert_eq!(data["provider"], "gemini");
+        assert_eq!(data["model"], "gemini-1.5-flash-latest");
+
+        // Concurrent crawls with different models each get their own model.
+        let (mini, full) = tokio::join!(
+            extract(&service, "openai:gpt-4o-mini"),
+            extract(&service, "openai:gpt-4o"),
+        );
+        assert_eq!(mini.unwrap()["model"], "gpt-4o-mini");
+        assert_eq!(full.unwrap()["model"], "gpt-4o");
+
+        assert_eq!(
+            service.list_models().await,
+            vec!["gemini-model", "openai:openai-model", "ollama:ollama-model"]
+        );
+
+        let openai_only = AIService::new(vec![echo("openai")]);
+        assert!(matches!(
+            extract(&openai_only, "gemini-1.5-flash-latest").await,
+            Err(AppError::UnknownModel(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn reports_usage_per_request() {
+        let service = AIService::new(vec![echo("openai")]);
+        let mut params = ScrapeParams::for_test("https://example.com");
+        params.model = "openai:gpt-4o-mini".to_string();
+
+        // The slower request finishes last, so shared usage state would be reported twice.
+        let budget = Budget::default();
+        let long_prompt = "x".repeat(40);
+        let (long, short) = tokio::join!(
+            service.extract_items(&params, "system", &long_prompt, None, &budget, None),
+            service.extract_items(&params, "system", "xx", None, &budget, None),
+        );
+        let (long, short) = (long.unwrap().usage_metadata, short.unwrap().usage_metadata);
+
+        assert_eq!((long.input_tokens, long.output_tokens), (40, 1));
+        assert_eq!((short.input_tokens, short.output_tokens), (2, 1));
+        assert!(long.total_cost > short.total_cost && short.total_cost > 0.0);
+    }
+
+    #[tokio::test]
+    async fn answers_repeated_requests_from_the_cache() {
+        let dir = std::env::temp_dir().join(format!("ai-cache-{}", uuid::Uuid::new_v4()));
+        let service = AIService::new(vec![echo("openai")]).with_cache(AiCache::new(&dir));
+        let mut params = ScrapeParams::for_test("https://example.com");
+        params.model = "openai:gpt-4o-mini".to_string();
+        let budget = Budget::default();
+        let mut extract = |mode: AiCacheMode, prompt: &'static str| {
+            params.ai_cache = mode;
+            let params = params.clone();
+            let (service, budget) = (&service, &budget);
+            async move {
+                service
+                    .extract_items(&params, "system", prompt, None, budget, None)
+                    .await
+                    .unwrap()
+            }
+        };
+
+        let first = extract(AiCacheMode::Use, "page").await;
+        assert_eq!(first.usage_metadata.cache_misses, 1);
+        assert!(first.usage_metadata.total_cost > 0.0);
+
+        let repeated = extract(AiCacheMode::Use, "page").await;
+        assert_eq!(repeated.data, first.data);
+        assert_eq!(repeated.usage_metadata.cache_hits, 1);
+        assert_eq!(repeated.usage_metadata.input_tokens, 0);
+        assert_eq!(repeated.usage_metadata.total_cost, 0.0);
+        assert_eq!(
+            repeated.usage_metadata.saved_cost,
+            first.usage_metadata.total_cost
+        );
+
+        let other = extract(AiCacheMode::Use, "other page").await;
+        assert_eq!(other.usage_metadata.cache_misses, 1);
+        let refreshed = extract(AiCacheMode::Refresh, "page").await;
+        assert_eq!(refreshed.usage_metadata.cache_misses, 1);
+        let bypassed = extract(AiCacheMode::Bypass, "page").await;
+        assert_eq!(
+            (
+                bypassed.usage_metadata.cache_hits,
+                bypassed.usage_metadata.cache_misses
+            ),
+            (0, 0)
+        );
+        assert!(bypassed.usage_metadata.total_cost > 0.0);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn replays_recorded_answers_for_free() {
+        let path = std::env::temp_dir().join(format!("ai-{}.warc", uuid::Uuid::new_v4()));
+        let service = AIService::new(vec![echo("openai")]);
+        let mut params = ScrapeParams::for_test("https://example.com");
+        params.model = "openai:gpt-4o-mini".to_string();
+
+        let recorder = Warc::record(&path).await.unwrap();
+        let recorded = service
+            .extract_items(
+                &params,
+                "system",
+                "page",
+                None,
+                &Budget::default(),
+                Some(&recorder),
+            )
+            .await
+            .unwrap();
+        drop(recorder);
+
+        // Nothing fits in this budget, but a replayed answer needs none of it.
+        let budget = Budget::new(Some(0.0), None);
+        let replay = Warc::replay(&path).await.unwrap();
+        tokio::fs::remove_file(&path).await.unwrap();
+        let replayed = service
+            .extract_items(&params, "system", "page", None, &budget, Some(&replay))
+            .await
+            .unwrap();
+
+        assert_eq!(replayed.data, recorded.data);
+        assert_eq!(replayed.usage_metadata.cache_hits, 1);
+        assert_eq!(replayed.usage_metadata.total_cost, 0.0);
+        assert_eq!(
+            replayed.usage_metadata.saved_cost,
+            recorded.usage_metadata.total_cost
+        );
+        assert!(!budget.is_exhausted());
+    }
+
+    #[tokio::test]
+    async fn refuses_requests_over_budget() {
+        let service = AIService::new(vec![echo("openai")]);
+        let mut params = ScrapeParams::for_test("https://example.com");
+        params.model = "openai:gpt-4o-mini".to_string();
+
+        // Even the shortest answer worth asking for would cost about $0.0006.
+        let budget = Budget::new(Some(0.0001), None);
+        assert!(matches!(
+            service
+                .extract_items(&params, "system", "user", None, &budget, None)
+                .await,
+            Err(AppError::BudgetExceeded(_))
+        ));
+        assert!(budget.is_exhausted());
+
+        // Settled requests only count what they used, so they keep fitting one after
+        // another, but a full-length answer in flight leaves too little for a second.
+        let budget = Budget::new(Some(0.005), None);
+        for _ in 0..3 {
+            service
+                .extract_items(&params, "system", "user", None, &budget, None)
+                .await
+                .unwrap();
+        }
+        let (first, second) = tokio::join!(
+            service.extract_items(&params, "system", "user", None, &budget, None),
+            service.extract_items(&params, "system", "user", None, &budget, None),
+        );
+        assert!(first.is_ok());
+        assert!(matches!(second, Err(AppError::BudgetExceeded(_))));
+    }
+
+    #[tokio::test]
+    async fn shortens_answers_to_fit_small_budgets() {
+        let service = AIService::new(vec![echo("openai")]);
+        let mut params = ScrapeParams::for_test("https://example.com");
+        params.model = "openai:gpt-4o-mini".to_string();
+
+        // Far below a full-length answer, but plenty for a small page.
+        let budget = Budget::new(None, Some(2_000));
+        let result = service
+            .extract_items(&params, "system", "<p>small page</p>", None, &budget, None)
+            .await
+            .unwrap();
+
+        assert_eq!(result.data["provider"], "openai");
+        assert!(!budget.is_exhausted());
+        assert!(budget.remaining_tokens().unwrap() > 1_900);
+    }
+
+    /// Answers with two records, the second of which has the wrong price type.
+    struct RecordsProvider;
+
+    #[async_trait]
+    impl AIProvider for RecordsProvider {
+        fn name(&self) -> &'static str {
+            "gemini"
+        }
+
+        async fn list_models(&self) -> Vec<String> {
+            vec![]
+        }
+
+        async fn process_request(&self, request: AiRequest) -> Result<AiResponse, AppError> {
+            let schema = request.response_schema.expect("no response schema");
+            assert_eq!(schema["properties"]["records"]["type"], "array");
+            Ok(AiResponse {
+                text: serde_json::json!({
+                    "records": [
+                        { "name": "Lamp", "price": 19.5 },
+                        { "name": "Chair", "price": "cheap" },
+                    ],
+                })
+                .to_string(),
+                input_tokens: 0,
+                cached_input_tokens: 0,
+                output_tokens: 0,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn validates_records_against_the_schema() {
+        let service = AIService::new(vec![Arc::new(RecordsProvider)]);
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": { "name": { "type": "string" }, "price": { "type": "number" } },
+            "required": ["name", "price"],
+        });
+
+        let result = service
+            .extract_items(
+                &ScrapeParams::for_test("https://example.com"),
+                "system",
+                "user",
+                Some(&schema),
+                &Budget::default(),
+                None,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(result.data.as_array().unwrap().len(), 2);
+        assert_eq!(result.validation_errors.len(), 1);
+        assert_eq!(result.validation_errors[0].index, 1);
+        assert_eq!(
+            result.validation_errors[0].errors,
+            vec!["/price: expected number, got string"]
+        );
+    }
+
+    #[tokio::test]
+    async fn maps_priced_models_to_gemini_models() {
+        assert!(matches!(gemini_model("gemini-pro"), Ok(Model::GeminiPro)));
+        assert!(matches!(
+            gemini_model("gemini-1.5-pro-latest"),
+            Ok(Model::Gemini1_5Pro)
+        ));
+        assert!(matches!(
+            gemini_model("gemini-1.5-flash-latest"),
+            Ok(Model::Gemini1_5Flash)
+        ));
+        assert!(matches!(
+            gemini_model("gemini-1.5-flash-8b-latest"),
+            Ok(Model::Custom(name)) if name == "gemini-1.5-flash-8b-latest"
+        ));
+
+        for model in GeminiAIProvider::new().list_models().await {
+            assert!(
+                gemini_model(&model).is_ok(),
+                "{} has no Gemini model",
+                model
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_models() {
+        assert!(matches!(
+            gemini_model("gemini-9-ultra"),
+            Err(AppError::UnknownModel(name)) if name == "gemini-9-ultra"
+        ));
+        assert!(matches!(
+            gemini_model("openai:gpt-4o"),
+            Err(AppError::UnknownModel(_))
+        ));
+        assert!(matches!(
+            calculate_price("gemini-9-ultra", 10, 0, 10),
+            Err(AppError::UnknownModel(_))
+        ));
+
+        let service = AIService::new(vec![Arc::new(GeminiAIProvider::new())]);
+        assert!(service.check_model("gemini-1.5-flash-latest").is_ok());
+        assert!(matches!(
+            service.check_model("gemini-9-ultra"),
+            Err(AppError::UnknownModel(_))
+        ));
+    }
+}
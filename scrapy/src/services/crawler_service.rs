@@ -1,43 +1,171 @@
-use crate::models::ScrapeParams;
+use crate::error::AppError;
+use crate::models::{CrawlReport, JobStatus, ScrapeParams};
+use crate::progress::ProgressReporter;
 use crate::spider::GenericSpider;
+use crate::store::Store;
 use crate::Crawler;
-use crate::{error::AppError, models::AiScrapingResult};
+use chrono::Utc;
 use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
 
-use super::{ai_service::GeminiAIProvider, AIService, WebSocketService};
+use super::{AIService, WebSocketService};
 
 pub struct CrawlerService {
     pub crawler: Crawler,
     pub websocket_service: Arc<WebSocketService>,
-    pub ai_service: Arc<AIService<GeminiAIProvider>>,
+    pub ai_service: Arc<AIService>,
+    /// Where crawls and their results are saved; unset in tests.
+    pub store: Option<Arc<Store>>,
 }
 
 impl CrawlerService {
     pub fn new(
         crawler: Crawler,
         websocket_service: Arc<WebSocketService>,
-        ai_service: Arc<AIService<GeminiAIProvider>>,
+        ai_service: Arc<AIService>,
     ) -> Self {
         Self {
             crawler,
             websocket_service,
             ai_service,
+            store: None,
         }
     }
 
-    pub async fn crawl(&self, params: ScrapeParams) -> Result<Vec<AiScrapingResult>, AppError> {
+    pub fn with_store(mut self, store: Arc<Store>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    pub async fn crawl(&self, params: ScrapeParams) -> Result<CrawlReport, AppError> {
+        let progress = self.progress_reporter(Uuid::new_v4());
+        let spider = self.spider(&params, progress.clone()).await?;
+
+        let (report, _) = self
+            .run(spider, params, progress, CancellationToken::new())
+            .await;
+        Ok(report)
+    }
+
+    pub fn progress_reporter(&self, crawl_id: Uuid) -> Arc<ProgressReporter> {
+        Arc::new(ProgressReporter::new(
+            crawl_id,
+            self.websocket_service.clone(),
+        ))
+    }
+
+    /// Builds the spider for `params`, reporting invalid parameters to subscribers and
+    /// saving the rejected crawl as failed.
+    pub async fn spider(
+        &self,
+        params: &ScrapeParams,
+        progress: Arc<ProgressReporter>,
+    ) -> Result<Arc<GenericSpider>, AppError> {
+        let spider = self.build_spider(params, progress.clone()).await;
+        let Err(e) = &spider else {
+            return spider;
+        };
+
+        progress.crawl_failed(e).await;
+        if let Some(store) = &self.store {
+            let (id, params, error) = (progress.crawl_id(), params.clone(), e.to_string());
+            if let Err(e) = store
+                .call(move |store| store.fail_crawl(id, &params, Utc::now(), &error))
+                .await
+            {
+                log::error!("Failed to save crawl {}: {}", id, e);
+            }
+        }
+        spider
+    }
+
+    async fn build_spider(
+        &self,
+        params: &ScrapeParams,
+        progress: Arc<ProgressReporter>,
+    ) -> Result<Arc<GenericSpider>, AppError> {
+        let ai_extraction = params.enable_scraping && params.extraction.is_none();
+        if ai_extraction || params.pagination_details.is_some() {
+            self.ai_service.check_model(&params.model)?;
+        }
+
         let selectors = vec!["body"];
-        let generic_spider = GenericSpider::new(selectors, self.ai_service.clone(), params.clone())?;
-        let spider = Arc::new(generic_spider);
+        let spider =
+            GenericSpider::new(selectors, self.ai_service.clone(), params.clone(), progress)
+                .await?;
+        Ok(Arc::new(spider))
+    }
+
+    /// Crawls until done or cancelled and returns the report with how the crawl ended, as
+    /// reported to subscribers and saved to the store.
+    pub async fn run(
+        &self,
+        spider: Arc<GenericSpider>,
+        params: ScrapeParams,
+        progress: Arc<ProgressReporter>,
+        cancel: CancellationToken,
+    ) -> (CrawlReport, JobStatus) {
+        log::info!("Starting crawl {} for {}", progress.crawl_id(), params.url);
+        if let Some(store) = &self.store {
+            let (id, params) = (progress.crawl_id(), params.clone());
+            let started_at = Utc::now();
+            if let Err(e) = store
+                .call(move |store| store.start_crawl(id, &params, started_at))
+                .await
+            {
+                log::error!("Failed to save crawl {}: {}", id, e);
+            }
+        }
+
+        // Running out of budget stops queueing pages, but extractions already under way
+        // keep their answers, and the crawl finishes normally with the partial results.
+        let stop = cancel.child_token();
+        let budget_watch = tokio::spawn({
+            let spider = spider.clone();
+            let progress = progress.clone();
+            let stop = stop.clone();
+            async move {
+                spider.budget().exhausted().await;
+                progress.budget_exhausted().await;
+                stop.cancel();
+            }
+        });
+
         self.crawler
             .crawl(
                 spider.clone(),
                 params,
+                progress.clone(),
+                cancel.clone(),
+                stop,
             )
             .await;
+        budget_watch.abort();
 
-        let results = spider.get_results().await;
+        let report = spider.report().await;
+        let status = if cancel.is_cancelled() {
+            progress.crawl_cancelled().await;
+            JobStatus::Cancelled
+        } else {
+            progress.crawl_finished(&report).await;
+            JobStatus::Completed
+        };
+
+        if let Some(store) = &self.store {
+            let (id, saved) = (progress.crawl_id(), report.clone());
+            if let Err(e) = store
+                .call(move |store| store.finish_crawl(id, status, &saved))
+                .await
+            {
+                log::error!(
+                    "Failed to save results of crawl {}: {}",
+                    progress.crawl_id(),
+                    e
+                );
+            }
+        }
 
-        Ok(results)
+        (report, status)
     }
 }
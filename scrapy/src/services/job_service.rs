@@ -0,0 +1,290 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use rocket::tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::export::{Export, ExportFormat};
+use crate::models::{CrawlJobInfo, JobStatus, ScrapeParams};
+use crate::progress::ProgressReporter;
+use crate::spider::GenericSpider;
+
+use super::CrawlerService;
+
+struct CrawlJob {
+    id: Uuid,
+    params: ScrapeParams,
+    started_at: DateTime<Utc>,
+    spider: Arc<GenericSpider>,
+    progress: Arc<ProgressReporter>,
+    cancel: CancellationToken,
+    state: Mutex<JobState>,
+}
+
+#[derive(Clone)]
+struct JobState {
+    status: JobStatus,
+    finished_at: Option<DateTime<Utc>>,
+}
+
+impl CrawlJob {
+    async fn info(&self) -> CrawlJobInfo {
+        let state = self.state.lock().await.clone();
+        self.info_at(state).await
+    }
+
+    /// The job's info with `state` as its status, e.g. as one request left it.
+    async fn info_at(
+        &self,
+        JobState {
+            status,
+            finished_at,
+        }: JobState,
+    ) -> CrawlJobInfo {
+        CrawlJobInfo {
+            id: self.id,
+            url: self.params.url.clone(),
+            status,
+            started_at: self.started_at,
+            finished_at,
+            counters: self.progress.counters(),
+            usage: self.progress.usage(),
+            results: self.spider.report().await.into_scraping_results(),
+        }
+    }
+}
+
+/// How long a finished job stays available, and how many finished jobs are kept at most.
+/// Their results remain in the store after that.
+const FINISHED_JOB_TTL: Duration = Duration::from_secs(60 * 60);
+const MAX_FINISHED_JOBS: usize = 100;
+
+/// Runs crawls in the background so that callers can poll or cancel them by id.
+pub struct JobService {
+    crawler_service: Arc<CrawlerService>,
+    jobs: Mutex<HashMap<Uuid, Arc<CrawlJob>>>,
+    finished_ttl: Duration,
+    max_finished: usize,
+}
+
+impl JobService {
+    pub fn new(crawler_service: Arc<CrawlerService>) -> Self {
+        Self {
+            crawler_service,
+            jobs: Mutex::new(HashMap::new()),
+            finished_ttl: FINISHED_JOB_TTL,
+            max_finished: MAX_FINISHED_JOBS,
+        }
+    }
+
+    /// Starts crawling `params` and returns the job id; progress messages use it as crawl id.
+    pub async fn start(&self, params: ScrapeParams) -> Result<Uuid, AppError> {
+        let id = Uuid::new_v4();
+        let progress = self.crawler_service.progress_reporter(id);
+        let spider = self
+            .crawler_service
+            .spider(&params, progress.clone())
+            .await?;
+
+        let job = Arc::new(CrawlJob {
+            id,
+            params: params.clone(),
+            started_at: Utc::now(),
+            spider,
+            progress,
+            cancel: CancellationToken::new(),
+            state: Mutex::new(JobState {
+                status: JobStatus::Running,
+                finished_at: None,
+            }),
+        });
+        {
+            let mut jobs = self.jobs.lock().await;
+            self.evict_finished(&mut jobs).await;
+            jobs.insert(id, job.clone());
+        }
+
+        let crawler_service = self.crawler_service.clone();
+        tokio::spawn(async move {
+            let (_, status) = crawler_service
+                .run(
+                    job.spider.clone(),
+                    params,
+                    job.progress.clone(),
+                    job.cancel.clone(),
+                )
+                .await;
+
+            // A cancellation requested after the crawl ended does not change how it ended.
+            let mut state = job.state.lock().await;
+            state.status = status;
+            state.finished_at = Some(Utc::now());
+            log::info!("Crawl job {} finished as {:?}", job.id, state.status);
+        });
+
+        Ok(id)
+    }
+
+    /// Drops finished jobs older than `finished_ttl`, then the oldest ones beyond
+    /// `max_finished`.
+    async fn evict_finished(&self, jobs: &mut HashMap<Uuid, Arc<CrawlJob>>) {
+        let mut finished = Vec::new();
+        for (id, job) in jobs.iter() {
+            if let Some(finished_at) = job.state.lock().await.finished_at {
+                finished.push((finished_at, *id));
+            }
+        }
+        finished.sort();
+
+        let expired = chrono::Duration::from_std(self.finished_ttl)
+            .ok()
+            .and_then(|ttl| Utc::now().checked_sub_signed(ttl));
+        let excess = finished.len().saturating_sub(self.max_finished);
+        for (i, (finished_at, id)) in finished.into_iter().enumerate() {
+            if i < excess || expired.is_some_and(|expired| finished_at < expired) {
+                jobs.remove(&id);
+            }
+        }
+    }
+
+    pub async fn get(&self, id: Uuid) -> Option<CrawlJobInfo> {
+        let job = self.jobs.lock().await.get(&id).cloned()?;
+        Some(job.info().await)
+    }
+
+    pub async fn export(&self, id: Uuid, format: ExportFormat) -> Option<Export> {
+        let job = self.jobs.lock().await.get(&id).cloned()?;
+        let results = job.spider.report().await.into_scraping_results();
+        Some(Export::new(format, &job.params, results))
+    }
+
+    /// Requests cancellation of a running job. Finished jobs are left as they are.
+    pub async fn cancel(&self, id: Uuid) -> Option<CrawlJobInfo> {
+        let job = self.jobs.lock().await.get(&id).cloned()?;
+
+        // The crawl may end as soon as it is cancelled, so the response reports the state
+        // this request left the job in.
+        let state = {
+            let mut state = job.state.lock().await;
+            if state.status == JobStatus::Running {
+                log::info!("Cancelling crawl job {}", id);
+                job.cancel.cancel();
+                state.status = JobStatus::Cancelling;
+            }
+            state.clone()
+        };
+
+        Some(job.info_at(state).await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::crawler::Crawler;
+    use crate::services::{AIService, WebSocketService};
+    use crate::test_support::{serve, TestResponse};
+
+    fn job_service() -> JobService {
+        let crawler_service = CrawlerService::new(
+            Crawler::new(Duration::from_millis(50), 1, 4, 4),
+            Arc::new(WebSocketService::new(16)),
+            Arc::new(AIService::new(vec![])),
+        );
+        JobService::new(Arc::new(crawler_service))
+    }
+
+    async fn wait_until_finished(jobs: &JobService, id: Uuid) -> CrawlJobInfo {
+        tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                let info = jobs.get(id).await.unwrap();
+                if info.finished_at.is_some() {
+                    return info;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("job did not finish")
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn cancels_a_running_crawl() {
+        // Every page links to ten more, so the crawl would not end on its own.
+        let base = serve(|request| {
+            let links: String = (0..10)
+                .map(|i| {
+                    format!(
+                        "<a href=\"{}/{}\">next</a>",
+                        request.path.trim_end_matches('/'),
+                        i
+                    )
+                })
+                .collect();
+            TestResponse::ok(&format!("<html><body>{}</body></html>", links))
+        })
+        .await;
+
+        let jobs = job_service();
+        let mut params = ScrapeParams::for_test(&format!("{}/", base));
+        params.follow_links = true;
+        let id = jobs.start(params).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let cancelling = jobs.cancel(id).await.unwrap();
+        assert_eq!(cancelling.status, JobStatus::Cancelling);
+
+        let info = wait_until_finished(&jobs, id).await;
+        assert_eq!(info.status, JobStatus::Cancelled);
+        assert!(info.counters.fetched > 0);
+        assert!(!info.results.is_empty());
+        assert!(jobs.get(Uuid::new_v4()).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn completes_and_keeps_results() {
+        let base = serve(|_| TestResponse::ok("<html><body>done</body></html>")).await;
+        let jobs = job_service();
+
+        let id = jobs
+            .start(ScrapeParams::for_test(&format!("{}/", base)))
+            .await
+            .unwrap();
+
+        let info = wait_until_finished(&jobs, id).await;
+        assert_eq!(info.status, JobStatus::Completed);
+        assert_eq!(info.results.len(), 1);
+        assert_eq!(info.results[0].status, Some(200));
+
+        let after_cancel = jobs.cancel(id).await.unwrap();
+        assert_eq!(after_cancel.status, JobStatus::Completed);
+    }
+
+    #[tokio::test]
+    async fn evicts_finished_jobs() {
+        let base = serve(|_| TestResponse::ok("<html><body>done</body></html>")).await;
+        let mut jobs = job_service();
+        jobs.max_finished = 1;
+        let params = ScrapeParams::for_test(&format!("{}/", base));
+
+        let first = jobs.start(params.clone()).await.unwrap();
+        wait_until_finished(&jobs, first).await;
+        let second = jobs.start(params.clone()).await.unwrap();
+        wait_until_finished(&jobs, second).await;
+
+        // Starting a job makes room by dropping the oldest finished one.
+        let third = jobs.start(params.clone()).await.unwrap();
+        assert!(jobs.get(first).await.is_none());
+        assert!(jobs.get(second).await.is_some());
+
+        wait_until_finished(&jobs, third).await;
+        jobs.finished_ttl = Duration::ZERO;
+        jobs.start(params).await.unwrap();
+        assert!(jobs.get(second).await.is_none());
+        assert!(jobs.get(third).await.is_none());
+    }
+}
@@ -1,8 +1,20 @@
+mod ai_cache;
+pub use ai_cache::AiCache;
+
 mod ai_service;
-pub use ai_service::{AIService, GeminiAIProvider};
+pub use ai_service::AIService;
+
+mod ollama_provider;
+pub use ollama_provider::OllamaProvider;
+
+mod openai_provider;
+pub use openai_provider::OpenAIProvider;
 
 mod crawler_service;
 pub use crawler_service::CrawlerService;
 
+mod job_service;
+pub use job_service::JobService;
+
 mod websocket_service;
 pub use websocket_service::WebSocketService;
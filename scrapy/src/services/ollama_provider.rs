@@ -0,0 +1,161 @@
+use std::{env, time::Duration};
+
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::error::AppError;
+
+use super::ai_service::{AIProvider, AiRequest, AiResponse};
+
+/// Talks to a local Ollama server. Local models have no pricing, so their usage costs nothing.
+pub struct OllamaProvider {
+    http_client: Client,
+    base_url: String,
+}
+
+#[derive(Deserialize)]
+struct ChatResponse {
+    message: ChatMessage,
+    #[serde(default)]
+    prompt_eval_count: u64,
+    #[serde(default)]
+    eval_count: u64,
+}
+
+#[derive(Deserialize)]
+struct ChatMessage {
+    content: String,
+}
+
+#[derive(Deserialize)]
+struct Tags {
+    models: Vec<Tag>,
+}
+
+#[derive(Deserialize)]
+struct Tag {
+    name: String,
+}
+
+impl OllamaProvider {
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            // Local models can take minutes on large pages.
+            http_client: Client::builder()
+                .timeout(Duration::from_secs(300))
+                .build()
+                .expect("ollama: Building HTTP client"),
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Configured when `OLLAMA_BASE_URL` is set, e.g. to `http://localhost:11434`.
+    pub fn from_env() -> Option<Self> {
+        env::var("OLLAMA_BASE_URL")
+            .ok()
+            .filter(|u| !u.is_empty())
+            .map(|base_url| Self::new(&base_url))
+    }
+}
+
+#[async_trait]
+impl AIProvider for OllamaProvider {
+    fn name(&self) -> &'static str {
+        "ollama"
+    }
+
+    /// The models pulled on the server; empty if it cannot be reached.
+    async fn list_models(&self) -> Vec<String> {
+        let tags = async {
+            self.http_client
+                .get(format!("{}/api/tags", self.base_url))
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<Tags>()
+                .await
+        };
+
+        match tags.await {
+            Ok(tags) => tags.models.into_iter().map(|tag| tag.name).collect(),
+            Err(e) => {
+                log::warn!("Failed to list Ollama models: {}", e);
+                vec![]
+            }
+        }
+    }
+
+    async fn process_request(&self, request: AiRequest) -> Result<AiResponse, AppError> {
+        let model = request.model;
+
+        let body = json!({
+            "model": model,
+            "messages": [
+                { "role": "system", "content": request.system_prompt },
+                { "role": "user", "content": request.user_prompt },
+            ],
+            "stream": false,
+            // Ollama constrains the output to a JSON Schema passed as `format`.
+            "format": request.response_schema.unwrap_or_else(|| json!("json")),
+            "options": { "num_predict": request.max_output_tokens },
+        });
+
+        let response = self
+            .http_client
+            .post(format!("{}/api/chat", self.base_url))
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| AppError::AI(e.to_string()))?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(AppError::AI(format!(
+                "{} answered {}: {}",
+                model, status, body
+            )));
+        }
+
+        let chat: ChatResponse = response
+            .json()
+            .await
+            .map_err(|e| AppError::AI(e.to_string()))?;
+
+        Ok(AiResponse {
+            text: chat.message.content,
+            input_tokens: chat.prompt_eval_count,
+            cached_input_tokens: 0,
+            output_tokens: chat.eval_count,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{serve, TestResponse};
+
+    #[tokio::test]
+    async fn lists_models_and_chats() {
+        let base = serve(|request| match request.path.as_str() {
+            "/api/tags" => TestResponse::ok(r#"{"models":[{"name":"llama3.1:8b"}]}"#),
+            "/api/chat" => TestResponse::ok(
+                r#"{"message":{"role":"assistant","content":"[]"},
+                    "prompt_eval_count":42,"eval_count":2}"#,
+            ),
+            _ => TestResponse::status(404),
+        })
+        .await;
+
+        let provider = OllamaProvider::new(&base);
+        assert_eq!(provider.list_models().await, vec!["llama3.1:8b"]);
+
+        let request =
+            provider.build_request("llama3.1:8b", "", "system".to_string(), "user".to_string());
+        let response = provider.process_request(request).await.unwrap();
+        assert_eq!(response.text, "[]");
+        assert_eq!((response.input_tokens, response.output_tokens), (42, 2));
+    }
+}
@@ -0,0 +1,211 @@
+use std::{env, time::Duration};
+
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::error::AppError;
+
+use super::ai_service::{AIProvider, AiRequest, AiResponse};
+
+const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+const DEFAULT_MODELS: &str = "gpt-4o-mini,gpt-4o";
+
+/// Talks to any server implementing OpenAI's chat completions API.
+pub struct OpenAIProvider {
+    http_client: Client,
+    base_url: String,
+    default_api_key: Option<String>,
+    models: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ChatCompletion {
+    choices: Vec<Choice>,
+    usage: Option<CompletionUsage>,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    message: ChoiceMessage,
+}
+
+#[derive(Deserialize)]
+struct ChoiceMessage {
+    content: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CompletionUsage {
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    prompt_tokens_details: Option<PromptTokensDetails>,
+}
+
+#[derive(Deserialize)]
+struct PromptTokensDetails {
+    #[serde(default)]
+    cached_tokens: u64,
+}
+
+impl OpenAIProvider {
+    pub fn new(base_url: &str, default_api_key: Option<String>, models: Vec<String>) -> Self {
+        Self {
+            http_client: Client::builder()
+                .timeout(Duration::from_secs(60))
+                .build()
+                .expect("openai: Building HTTP client"),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            default_api_key,
+            models,
+        }
+    }
+
+    /// Configured when `OPENAI_API_KEY` or `OPENAI_BASE_URL` is set. `OPENAI_MODELS` is a
+    /// comma-separated list of the models to advertise.
+    pub fn from_env() -> Option<Self> {
+        let api_key = env::var("OPENAI_API_KEY").ok().filter(|k| !k.is_empty());
+        let base_url = env::var("OPENAI_BASE_URL").ok().filter(|u| !u.is_empty());
+        if api_key.is_none() && base_url.is_none() {
+            return None;
+        }
+
+        let models = env::var("OPENAI_MODELS").unwrap_or_else(|_| DEFAULT_MODELS.to_string());
+        Some(Self::new(
+            base_url.as_deref().unwrap_or(DEFAULT_BASE_URL),
+            api_key,
+            models
+                .split(',')
+                .map(str::trim)
+                .filter(|m| !m.is_empty())
+                .map(String::from)
+                .collect(),
+        ))
+    }
+}
+
+#[async_trait]
+impl AIProvider for OpenAIProvider {
+    fn name(&self) -> &'static str {
+        "openai"
+    }
+
+    async fn list_models(&self) -> Vec<String> {
+        self.models.clone()
+    }
+
+    async fn process_request(&self, request: AiRequest) -> Result<AiResponse, AppError> {
+        let model = request.model;
+        let api_key = Some(request.api_key)
+            .filter(|k| !k.is_empty())
+            .or_else(|| self.default_api_key.clone());
+
+        let response_format = match request.response_schema {
+            Some(schema) => json!({
+                "type": "json_schema",
+                "json_schema": { "name": "records", "schema": schema },
+            }),
+            None => json!({ "type": "json_object" }),
+        };
+
+        let body = json!({
+            "model": model,
+            "messages": [
+                { "role": "system", "content": request.system_prompt },
+                { "role": "user", "content": request.user_prompt },
+            ],
+            "max_tokens": request.max_output_tokens,
+            "response_format": response_format,
+        });
+
+        let mut http_request = self
+            .http_client
+            .post(format!("{}/chat/completions", self.base_url))
+            .json(&body);
+        if let Some(api_key) = api_key {
+            http_request = http_request.bearer_auth(api_key);
+        }
+
+        let response = http_request
+            .send()
+            .await
+            .map_err(|e| AppError::AI(e.to_string()))?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(AppError::AI(format!(
+                "{} answered {}: {}",
+                model, status, body
+            )));
+        }
+
+        let completion: ChatCompletion = response
+            .json()
+            .await
+            .map_err(|e| AppError::AI(e.to_string()))?;
+
+        let text = completion
+            .choices
+            .into_iter()
+            .next()
+            .and_then(|choice| choice.message.content)
+            .ok_or_else(|| AppError::AI("No valid response from AI".to_string()))?;
+        let (input_tokens, cached_input_tokens, output_tokens) = completion
+            .usage
+            .map(|usage| {
+                let cached = usage.prompt_tokens_details.map_or(0, |d| d.cached_tokens);
+                (usage.prompt_tokens, cached, usage.completion_tokens)
+            })
+            .unwrap_or_default();
+
+        Ok(AiResponse {
+            text,
+            input_tokens,
+            cached_input_tokens,
+            output_tokens,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{serve, TestResponse};
+
+    #[tokio::test]
+    async fn sends_chat_completions_and_returns_usage() {
+        let base = serve(|request| {
+            assert_eq!(request.path, "/v1/chat/completions");
+            assert_eq!(
+                request.headers.get("authorization").map(String::as_str),
+                Some("Bearer sk-test")
+            );
+            TestResponse::ok(
+                r#"{"choices":[{"message":{"content":"{\"items\":[]}"}}],
+                    "usage":{"prompt_tokens":1000,"completion_tokens":100,
+                        "prompt_tokens_details":{"cached_tokens":600}}}"#,
+            )
+        })
+        .await;
+
+        let provider = OpenAIProvider::new(&format!("{}/v1/", base), None, vec![]);
+        let request = provider.build_request(
+            "gpt-4o-mini",
+            "sk-test",
+            "system".to_string(),
+            "user".to_string(),
+        );
+
+        let response = provider.process_request(request).await.unwrap();
+        assert_eq!(response.text, r#"{"items":[]}"#);
+        assert_eq!(
+            (
+                response.input_tokens,
+                response.cached_input_tokens,
+                response.output_tokens
+            ),
+            (1000, 600, 100)
+        );
+    }
+}
@@ -1,15 +1,40 @@
-use std::{sync::Arc, time::Duration};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use async_trait::async_trait;
-use reqwest::Client;
+use chrono::Utc;
+use futures_util::{stream, StreamExt};
+use reqwest::{
+    header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, LOCATION},
+    redirect, Client, StatusCode,
+};
 use scraper::{Html, Selector};
 use serde::Serialize;
+use serde_json::Value;
 use tokio::sync::Mutex;
+use url::Url;
 
 use crate::{
+    budget::Budget,
+    chunk::{chunk_content, merge_results},
+    clean::clean,
+    constants::{
+        DEFAULT_MAX_PAGINATION_PAGES, DEFAULT_USER_AGENT, MAX_PAGINATION_CANDIDATES, MAX_REDIRECTS,
+    },
     error::AppError,
-    models::{AiScrapingResult, ScrapeParams},
-    services::{AIService, GeminiAIProvider},
+    extract::SelectorExtractor,
+    http_cache::{CacheMode, CachedResponse, HttpCache},
+    links::{extract_links, LinkFilter},
+    models::{
+        AiScrapingResult, CacheStatus, ContentSize, CrawlReport, PageMetadata, PaginationInfo,
+        ScrapeParams, SkippedPage, UsageMetadata,
+    },
+    pagination::{find_pager_next, find_rel_next, link_candidates, LinkCandidate},
+    progress::ProgressReporter,
+    retry::send_with_retry,
+    robots::RobotsCache,
+    schema::{check_schema, validate_records},
+    services::AIService,
+    warc::Warc,
 };
 
 #[async_trait]
@@ -21,62 +46,514 @@ pub trait Spider: Send + Sync {
     fn start_urls(&self) -> Vec<String>;
     async fn scrape(&self, url: String) -> Result<(Vec<Self::Item>, Vec<String>), Self::Error>;
     async fn process(&self, item: Self::Item) -> Result<(), Self::Error>;
+
+    /// Minimum pause the site asks for between requests to `url`'s host, if any.
+    async fn crawl_delay(&self, _url: &str) -> Option<Duration> {
+        None
+    }
+}
+
+/// Content selected from a fetched page, handed from `scrape` to `process`.
+#[derive(Debug, Serialize)]
+pub struct Page {
+    pub url: String,
+    /// The selected element's content after `ScrapeParams::cleaning`.
+    pub html: String,
+    pub content_size: ContentSize,
+}
+
+/// Progress along the chain of "next page" links that starts at the start URL.
+#[derive(Default)]
+struct PaginationState {
+    visited: Vec<String>,
+    next: Option<String>,
+    /// Hints of pages scraped before the chain reached them, e.g. through link discovery,
+    /// so that the chain can continue from them when it does.
+    scraped: HashMap<String, NextPageHints>,
+}
+
+/// What became of the page that continues the pagination chain.
+enum NextPage {
+    /// It still has to be scraped.
+    Queued,
+    /// It was already scraped and joined the chain; these are its hints.
+    Scraped(NextPageHints),
 }
 
+/// Where a page fetch ended up, recorded whether or not it succeeded.
+struct FetchTrace {
+    /// One for the fetch plus every retry of any hop; redirects are in `redirect_chain`.
+    attempts: u32,
+    final_url: Url,
+    status: Option<u16>,
+    redirect_chain: Vec<String>,
+    /// Where the last response came from, when the page cache is on.
+    cache: Option<CacheStatus>,
+}
+
+impl FetchTrace {
+    /// Records a hop that took `attempts` requests; only its retries add to the count.
+    fn count_attempts(&mut self, attempts: u32) {
+        self.attempts = self.attempts.max(1) + attempts.saturating_sub(1);
+    }
+}
+
+/// "Next page" hints gathered from a parsed document.
+struct NextPageHints {
+    rel_next: Option<Url>,
+    pager_next: Option<Url>,
+    candidates: Vec<LinkCandidate>,
+}
+
+/// Reported as the model of results produced by `ScrapeParams::extraction`.
+const SELECTOR_MODEL: &str = "selectors";
+
 pub struct GenericSpider {
     http_client: Client,
     selectors: Vec<Selector>,
-    ai_service: Arc<AIService<GeminiAIProvider>>,
+    link_filter: LinkFilter,
+    extractor: Option<SelectorExtractor>,
+    http_cache: Option<HttpCache>,
+    warc: Option<Arc<Warc>>,
+    robots: RobotsCache,
+    ai_service: Arc<AIService>,
     scrape_params: ScrapeParams,
+    pagination: Mutex<PaginationState>,
+    skipped: Mutex<Vec<SkippedPage>>,
+    pages: Mutex<Vec<PageMetadata>>,
+    /// AI usage of every request made for a page, including pagination resolution.
+    usage: Mutex<HashMap<String, UsageMetadata>>,
+    budget: Budget,
+    progress: Arc<ProgressReporter>,
     result: Arc<Mutex<Vec<AiScrapingResult>>>,
 }
 
 impl GenericSpider {
-    pub fn new(
+    pub async fn new(
         selectors: Vec<&str>,
-        ai_service: Arc<AIService<GeminiAIProvider>>,
+        ai_service: Arc<AIService>,
         scrape_params: ScrapeParams,
+        progress: Arc<ProgressReporter>,
     ) -> Result<Self, AppError> {
         let http_timeout = Duration::from_secs(6);
+        let user_agent = scrape_params
+            .user_agent
+            .clone()
+            .unwrap_or_else(|| DEFAULT_USER_AGENT.to_string());
+        // Redirects are followed by `fetch` so that the chain can be reported.
         let http_client = Client::builder()
             .timeout(http_timeout)
+            .user_agent(&user_agent)
+            .redirect(redirect::Policy::none())
             .build()
             .expect("spiders/general: Building HTTP client");
+        let robots_client = Client::builder()
+            .timeout(http_timeout)
+            .user_agent(&user_agent)
+            .build()
+            .expect("spiders/general: Building robots.txt HTTP client");
+        let mut robots = RobotsCache::new(robots_client, &user_agent);
 
         let selectors = selectors
             .into_iter()
             .map(|s| Selector::parse(s).unwrap())
             .collect();
 
+        let link_filter = LinkFilter::new(&scrape_params)?;
+        let extractor = scrape_params
+            .extraction
+            .as_ref()
+            .map(SelectorExtractor::new)
+            .transpose()?;
+        if let Some(schema) = &scrape_params.schema {
+            check_schema(schema).map_err(|e| AppError::InvalidParams(format!("schema: {}", e)))?;
+        }
+        // Opened last, so that a crawl rejected for other reasons does not touch the file.
+        let warc = match &scrape_params.warc {
+            Some(options) => Some(Arc::new(Warc::open(options).await?)),
+            None => None,
+        };
+        if let Some(warc) = &warc {
+            robots = robots.with_warc(warc.clone());
+        }
+        let http_cache = (scrape_params.cache.mode != CacheMode::Off && warc.is_none())
+            .then(HttpCache::from_env);
+        let budget = Budget::new(scrape_params.max_cost_usd, scrape_params.max_tokens);
+
         Ok(Self {
             http_client,
             selectors,
+            link_filter,
+            extractor,
+            http_cache,
+            warc,
+            robots,
             ai_service,
             scrape_params,
+            pagination: Mutex::new(PaginationState::default()),
+            skipped: Mutex::new(vec![]),
+            pages: Mutex::new(vec![]),
+            usage: Mutex::new(HashMap::new()),
+            budget,
+            progress,
             result: Arc::new(Mutex::new(vec![])),
         })
     }
 
     fn build_prompt(&self, html: &str) -> String {
-        format!(
-            "HTML Content: {}\n\nExtract the following information: {:?}",
-            html, self.scrape_params.tags
-        )
+        match &self.scrape_params.schema {
+            Some(_) => format!(
+                "HTML Content: {}\n\nExtract every record described by the response schema.",
+                html
+            ),
+            None => format!(
+                "HTML Content: {}\n\nExtract the following information: {:?}",
+                html, self.scrape_params.tags
+            ),
+        }
     }
 
     fn build_system_prompt(&self) -> String {
-        "You are an AI assistant specialized in web scraping. Extract the requested information from the provided HTML content and return it as a JSON array or object.".to_string()
+        match &self.scrape_params.schema {
+            Some(_) => "You are an AI assistant specialized in web scraping. Extract every matching record from the provided HTML content and return a JSON object of the form {\"records\": [...]}, using null for fields the page does not provide.".to_string(),
+            None => "You are an AI assistant specialized in web scraping. Extract the requested information from the provided HTML content and return it as a JSON array or object.".to_string(),
+        }
     }
 
     pub async fn get_results(&self) -> Vec<AiScrapingResult> {
         let results = self.result.lock().await;
         results.clone()
     }
+
+    /// Pages that were not fetched, with the reason.
+    pub async fn get_skipped(&self) -> Vec<SkippedPage> {
+        self.skipped.lock().await.clone()
+    }
+
+    /// Every page fetch attempted, successful or not.
+    pub async fn get_pages(&self) -> Vec<PageMetadata> {
+        self.pages.lock().await.clone()
+    }
+
+    /// Fetches `url`, following redirects and retrying transient failures, and returns
+    /// the body if the final status is accepted by `accepted_status_classes`.
+    async fn fetch(&self, url: &Url) -> (FetchTrace, Result<String, AppError>) {
+        let mut trace = FetchTrace {
+            attempts: 0,
+            final_url: url.clone(),
+            status: None,
+            redirect_chain: vec![],
+            cache: None,
+        };
+
+        let result = loop {
+            let response = match self.get(&mut trace).await {
+                Ok(response) => response,
+                Err(e) => break Err(e),
+            };
+            let status = StatusCode::from_u16(response.status).unwrap_or_default();
+            trace.status = Some(response.status);
+
+            let location = response
+                .header(LOCATION.as_str())
+                .and_then(|value| trace.final_url.join(value).ok());
+
+            if let (true, Some(location)) = (status.is_redirection(), location) {
+                if trace.redirect_chain.len() < MAX_REDIRECTS {
+                    trace.redirect_chain.push(trace.final_url.to_string());
+                    trace.final_url = location;
+                    continue;
+                }
+                log::warn!("Giving up on {} after {} redirects", url, MAX_REDIRECTS);
+            }
+
+            if !self.scrape_params.accepts_status(status.as_u16()) {
+                break Err(AppError::HttpStatus {
+                    url: trace.final_url.to_string(),
+                    status: status.as_u16(),
+                });
+            }
+
+            break Ok(response.body);
+        };
+
+        (trace, result)
+    }
+
+    /// Gets `trace.final_url` from the WARC file being replayed, or else from the page
+    /// cache or the site, according to `ScrapeParams::cache`. Stale cache entries are
+    /// revalidated with their `ETag` and `Last-Modified` headers.
+    async fn get(&self, trace: &mut FetchTrace) -> Result<CachedResponse, AppError> {
+        let url = trace.final_url.clone();
+        if let Some(warc) = self.warc.as_ref().filter(|warc| warc.is_replaying()) {
+            trace.count_attempts(1);
+            return warc
+                .response(&url)
+                .ok_or_else(|| AppError::NotRecorded(url.to_string()));
+        }
+
+        let options = &self.scrape_params.cache;
+        let cached = match &self.http_cache {
+            Some(cache) => cache.get(&url).await,
+            None => None,
+        };
+
+        match &cached {
+            Some(cached)
+                if options.mode == CacheMode::Offline
+                    || cached.is_fresh(Duration::from_secs(options.ttl_secs)) =>
+            {
+                trace.cache = Some(CacheStatus::Hit);
+                return Ok(cached.clone());
+            }
+            None if options.mode == CacheMode::Offline => {
+                return Err(AppError::CacheMiss(url.to_string()));
+            }
+            _ => {}
+        }
+
+        let (response, attempts) = send_with_retry(&self.scrape_params.retry, || {
+            let mut request = self.http_client.get(url.clone());
+            if let Some(cached) = &cached {
+                if let Some(etag) = cached.header(ETAG.as_str()) {
+                    request = request.header(IF_NONE_MATCH, etag);
+                }
+                if let Some(modified) = cached.header(LAST_MODIFIED.as_str()) {
+                    request = request.header(IF_MODIFIED_SINCE, modified);
+                }
+            }
+            request
+        })
+        .await;
+        trace.count_attempts(attempts);
+        let response = response?;
+
+        let (response, status) = match cached {
+            Some(mut cached) if response.status() == StatusCode::NOT_MODIFIED => {
+                cached.fetched_at = Utc::now();
+                (cached, CacheStatus::Revalidated)
+            }
+            _ => {
+                let status = response.status().as_u16();
+                let headers = response.headers().clone();
+                let body = response.bytes().await?;
+                if let Some(warc) = &self.warc {
+                    if let Err(e) = warc.record_response(&url, status, &headers, &body).await {
+                        log::warn!("Failed to record {}: {}", url, e);
+                    }
+                }
+                (
+                    CachedResponse::from_bytes(&url, status, &headers, &body),
+                    CacheStatus::Miss,
+                )
+            }
+        };
+
+        // Error responses are not stored, so that they are retried on the next crawl.
+        if let Some(cache) = &self.http_cache {
+            trace.cache = Some(status);
+            if response.status < 400 {
+                if let Err(e) = cache.put(&response).await {
+                    log::warn!("Failed to cache {}: {}", url, e);
+                }
+            }
+        }
+
+        Ok(response)
+    }
+
+    /// Everything gathered so far; partial while the crawl is still running.
+    pub async fn report(&self) -> CrawlReport {
+        let page_usage = self.usage.lock().await.clone();
+        let mut usage = UsageMetadata::default();
+        for page in page_usage.values() {
+            usage.add(page);
+        }
+
+        let mut pages = self.get_pages().await;
+        for page in &mut pages {
+            if let Some(page_usage) = page_usage.get(&page.url) {
+                page.usage = page_usage.clone();
+            }
+        }
+
+        let pagination = if self.scrape_params.enable_pagination {
+            Some(PaginationInfo::new(
+                self.get_pagination_pages().await,
+                &pages,
+            ))
+        } else {
+            None
+        };
+
+        CrawlReport {
+            results: self.get_results().await,
+            pagination,
+            skipped: self.get_skipped().await,
+            pages,
+            usage,
+            budget_limited: self.budget.is_exhausted(),
+        }
+    }
+
+    /// Whether pages are only read from the page cache.
+    fn offline(&self) -> bool {
+        self.http_cache.is_some() && self.scrape_params.cache.mode == CacheMode::Offline
+    }
+
+    /// The crawl's AI budget, from `ScrapeParams::max_cost_usd` and `max_tokens`.
+    pub fn budget(&self) -> &Budget {
+        &self.budget
+    }
+
+    /// Adds the usage of an AI request made for the page at `url` to the page and crawl totals.
+    async fn record_usage(&self, url: &str, usage: &UsageMetadata) {
+        self.usage
+            .lock()
+            .await
+            .entry(url.to_string())
+            .or_default()
+            .add(usage);
+        self.progress.usage_recorded(usage);
+    }
+
+    /// Pages visited by following "next page" links, starting with the start URL.
+    pub async fn get_pagination_pages(&self) -> Vec<String> {
+        self.pagination.lock().await.visited.clone()
+    }
+
+    /// Records `url` as visited and hands back its hints if it is the page the pagination
+    /// chain expects next. Other pages keep their hints in case the chain reaches them later.
+    async fn enter_pagination(&self, url: &str, hints: NextPageHints) -> Option<NextPageHints> {
+        let mut state = self.pagination.lock().await;
+        let expected = match &state.next {
+            Some(next) => next == url,
+            None => state.visited.is_empty() && url == self.scrape_params.url,
+        };
+
+        if expected {
+            state.visited.push(url.to_string());
+            state.next = None;
+            Some(hints)
+        } else {
+            if !state.visited.iter().any(|v| v == url) {
+                state.scraped.insert(url.to_string(), hints);
+            }
+            None
+        }
+    }
+
+    /// Continues the chain with `url`, unless the page limit is reached or the chain loops.
+    /// A page scraped earlier joins the chain at once.
+    async fn queue_next_page(&self, url: &str) -> Option<NextPage> {
+        let limit = self
+            .scrape_params
+            .max_pagination_pages
+            .unwrap_or(DEFAULT_MAX_PAGINATION_PAGES);
+
+        let mut state = self.pagination.lock().await;
+        if state.visited.len() >= limit || state.visited.iter().any(|v| v == url) {
+            return None;
+        }
+
+        if let Some(hints) = state.scraped.remove(url) {
+            state.visited.push(url.to_string());
+            return Some(NextPage::Scraped(hints));
+        }
+
+        state.next = Some(url.to_string());
+        Some(NextPage::Queued)
+    }
+
+    async fn find_next_page(&self, url: &str, hints: NextPageHints) -> Option<String> {
+        if let Some(url) = hints.rel_next {
+            return Some(url.to_string());
+        }
+
+        if let Some(details) = &self.scrape_params.pagination_details {
+            if !details.trim().is_empty() && !hints.candidates.is_empty() {
+                let resolved = self
+                    .resolve_pagination_details(url, details, &hints.candidates)
+                    .await;
+                if resolved.is_some() {
+                    return resolved;
+                }
+            }
+        }
+
+        hints.pager_next.map(String::from)
+    }
+
+    /// Stores records built by the selector extractor like an AI result that cost nothing.
+    async fn push_selector_records(&self, url: &str, records: Vec<Value>) {
+        let now = Utc::now();
+        let result = AiScrapingResult {
+            url: url.to_string(),
+            model: SELECTOR_MODEL.to_string(),
+            start_time: now,
+            end_time: Some(now),
+            validation_errors: match &self.scrape_params.schema {
+                Some(schema) => validate_records(schema, &records),
+                None => vec![],
+            },
+            data: Value::Array(records),
+            usage_metadata: UsageMetadata::default(),
+            content_size: None,
+        };
+
+        self.progress.extraction_finished(&result).await;
+        self.result.lock().await.push(result);
+    }
+
+    /// Asks the AI provider which of the page's links matches the caller's description of
+    /// the pagination control. Only URLs that actually appear on the page are accepted.
+    async fn resolve_pagination_details(
+        &self,
+        url: &str,
+        details: &str,
+        candidates: &[LinkCandidate],
+    ) -> Option<String> {
+        let system_prompt = "You are an AI assistant specialized in web scraping. Given a numbered list of links found on a web page and a description of how the site paginates, identify the link that leads to the next page. Return a JSON object of the form {\"nextPageUrl\": \"<url>\"}, or {\"nextPageUrl\": null} if there is no next page.";
+
+        let links = candidates
+            .iter()
+            .enumerate()
+            .map(|(i, c)| format!("{}. \"{}\" -> {}", i + 1, c.text, c.href))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let user_prompt = format!("Pagination details: {}\n\nLinks:\n{}", details, links);
+
+        match self
+            .ai_service
+            .extract_items(
+                &self.scrape_params,
+                system_prompt,
+                &user_prompt,
+                None,
+                &self.budget,
+                self.warc.as_deref(),
+            )
+            .await
+        {
+            Ok(result) => {
+                self.record_usage(url, &result.usage_metadata).await;
+                let next = result.data.get("nextPageUrl").and_then(Value::as_str)?;
+                candidates
+                    .iter()
+                    .find(|c| c.href == next)
+                    .map(|c| c.href.clone())
+            }
+            Err(e) => {
+                log::warn!("Failed to resolve pagination details: {}", e);
+                None
+            }
+        }
+    }
 }
 
 #[async_trait]
 impl Spider for GenericSpider {
-    type Item = String;
+    type Item = Page;
     type Error = AppError;
 
     fn name(&self) -> String {
@@ -88,29 +565,176 @@ impl Spider for GenericSpider {
     }
 
     async fn scrape(&self, url: String) -> Result<(Vec<Self::Item>, Vec<String>), Self::Error> {
-        let res = self.http_client.get(&url).send().await?;
-        let html = res.text().await?;
-        let document = Html::parse_document(&html);
+        let target = Url::parse(&url)
+            .map_err(|e| AppError::InvalidParams(format!("url '{}': {}", url, e)))?;
+
+        // Offline crawls only revisit cached pages, which were allowed when they were fetched.
+        if !self.offline() && !self.robots.is_allowed(&target).await {
+            log::info!("Skipping {}: disallowed by robots.txt", url);
+            self.progress
+                .url_skipped(&url, "disallowed by robots.txt")
+                .await;
+            self.skipped.lock().await.push(SkippedPage {
+                url,
+                reason: "disallowed by robots.txt".to_string(),
+            });
+            return Ok((vec![], vec![]));
+        }
+
+        let (trace, fetched) = self.fetch(&target).await;
+
+        self.pages.lock().await.push(PageMetadata {
+            url: url.clone(),
+            attempts: trace.attempts,
+            error: fetched.as_ref().err().map(ToString::to_string),
+            final_url: Some(trace.final_url.to_string()),
+            status: trace.status,
+            redirect_chain: trace.redirect_chain,
+            cache: trace.cache,
+            usage: UsageMetadata::default(),
+        });
 
-        let mut items = Vec::new();
+        let html = fetched?;
+        self.progress.url_fetched(&url, trace.status).await;
+        let page_url = trace.final_url;
 
-        for selector in &self.selectors {
-            for element in document.select(selector) {
-                items.push(element.inner_html());
+        let (items, mut new_urls, hints, records) = {
+            let document = Html::parse_document(&html);
+
+            let mut items = Vec::new();
+
+            for selector in &self.selectors {
+                for element in document.select(selector) {
+                    let html = clean(element, &self.scrape_params.cleaning);
+                    items.push(Page {
+                        url: url.clone(),
+                        content_size: ContentSize {
+                            original_bytes: element.inner_html().len(),
+                            cleaned_bytes: html.len(),
+                        },
+                        html,
+                    });
+                }
+            }
+
+            let new_urls: Vec<String> = if self.scrape_params.follow_links {
+                extract_links(&document, &page_url)
+                    .into_iter()
+                    .filter(|link| self.link_filter.allows(link))
+                    .map(String::from)
+                    .collect()
+            } else {
+                vec![]
+            };
+
+            let hints = self.scrape_params.enable_pagination.then(|| NextPageHints {
+                rel_next: find_rel_next(&document, &page_url),
+                pager_next: find_pager_next(&document, &page_url),
+                candidates: if self.scrape_params.pagination_details.is_some() {
+                    link_candidates(&document, &page_url, MAX_PAGINATION_CANDIDATES)
+                } else {
+                    vec![]
+                },
+            });
+
+            let records = self
+                .extractor
+                .as_ref()
+                .filter(|_| self.scrape_params.enable_scraping)
+                .map(|extractor| extractor.extract(&document));
+
+            (items, new_urls, hints, records)
+        };
+
+        if let Some(records) = records {
+            self.push_selector_records(&url, records).await;
+        }
+
+        let mut chain = match hints {
+            Some(hints) => self.enter_pagination(&url, hints).await,
+            None => None,
+        };
+        let mut from = url.clone();
+        while let Some(hints) = chain.take() {
+            let Some(next) = self.find_next_page(&from, hints).await else {
+                break;
+            };
+            match self.queue_next_page(&next).await {
+                Some(NextPage::Queued) => {
+                    log::debug!("following pagination from {} to {}", from, next);
+                    new_urls.push(next);
+                }
+                Some(NextPage::Scraped(hints)) => {
+                    log::debug!("pagination from {} reached scraped page {}", from, next);
+                    chain = Some(hints);
+                    from = next;
+                }
+                None => {}
             }
         }
 
-        Ok((items, vec![]))
+        Ok((items, new_urls))
     }
 
-    async fn process(&self, html: Self::Item) -> Result<(), Self::Error> {
-        if self.scrape_params.enable_scraping {
+    async fn process(&self, page: Self::Item) -> Result<(), Self::Error> {
+        if self.scrape_params.enable_scraping
+            && self.extractor.is_none()
+            && !self.budget.is_exhausted()
+        {
+            let chunking = &self.scrape_params.chunking;
+            let schema = self.scrape_params.schema.as_ref();
+            let chunks = chunk_content(
+                &page.html,
+                self.scrape_params.cleaning.produces_html(),
+                chunking.max_chunk_tokens,
+            );
+            self.progress
+                .extraction_started(&page.url, &page.content_size, chunks.len())
+                .await;
+
             let system_prompt = self.build_system_prompt();
-            let user_prompt = self.build_prompt(&html);
-            let result = self
-                .ai_service
-                .extract_items(&self.scrape_params, &system_prompt, &user_prompt)
-                .await?;
+            let outcomes: Vec<Result<AiScrapingResult, AppError>> = stream::iter(chunks)
+                .map(|chunk| {
+                    let system_prompt = &system_prompt;
+                    async move {
+                        let user_prompt = self.build_prompt(&chunk);
+                        self.ai_service
+                            .extract_items(
+                                &self.scrape_params,
+                                system_prompt,
+                                &user_prompt,
+                                schema,
+                                &self.budget,
+                                self.warc.as_deref(),
+                            )
+                            .await
+                    }
+                })
+                .buffered(chunking.max_concurrent_chunks.max(1))
+                .collect()
+                .await;
+
+            // Chunks that failed are reported; the page only fails if none succeeded.
+            // Chunks refused by the budget are left out silently.
+            let mut extracted = Vec::new();
+            let mut first_error = None;
+            for outcome in outcomes {
+                match outcome {
+                    Ok(result) => extracted.push(result),
+                    Err(AppError::BudgetExceeded(_)) => {}
+                    Err(e) => {
+                        self.progress.extraction_failed(&page.url, &e).await;
+                        first_error.get_or_insert(e);
+                    }
+                }
+            }
+            let Some(mut result) = merge_results(extracted, schema) else {
+                return first_error.map_or(Ok(()), Err);
+            };
+            self.record_usage(&page.url, &result.usage_metadata).await;
+            result.url = page.url;
+            result.content_size = Some(page.content_size);
+            self.progress.extraction_finished(&result).await;
 
             let mut results = self.result.lock().await;
             results.push(result);
@@ -118,4 +742,280 @@ impl Spider for GenericSpider {
 
         Ok(())
     }
+
+    async fn crawl_delay(&self, url: &str) -> Option<Duration> {
+        let replaying = self.warc.as_ref().is_some_and(|warc| warc.is_replaying());
+        if self.offline() || replaying {
+            return None;
+        }
+        let url = Url::parse(url).ok()?;
+        self.robots.crawl_delay(&url).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::StatusClass;
+    use crate::test_support::{serve, TestResponse};
+
+    async fn spider(params: ScrapeParams) -> GenericSpider {
+        let ai_service = Arc::new(AIService::new(vec![]));
+        GenericSpider::new(
+            vec!["body"],
+            ai_service,
+            params,
+            Arc::new(ProgressReporter::silent()),
+        )
+        .await
+        .unwrap()
+    }
+
+    async fn site() -> String {
+        serve(|request| match request.path.as_str() {
+            "/old" => TestResponse::status(301).with_header("Location", "/moved"),
+            "/moved" => TestResponse::status(302).with_header("Location", "/new"),
+            "/new" => TestResponse::ok("<html><body>fresh</body></html>"),
+            _ => TestResponse::status(404).with_body("<html><body>not here</body></html>"),
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn records_redirect_chain_and_final_status() {
+        let base = site().await;
+        let spider = spider(ScrapeParams::for_test(&format!("{}/old", base))).await;
+
+        let (items, _) = spider.scrape(format!("{}/old", base)).await.unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].html, "fresh");
+
+        let page = &spider.get_pages().await[0];
+        assert_eq!(page.status, Some(200));
+        assert_eq!(
+            page.final_url.as_deref(),
+            Some(format!("{}/new", base).as_str())
+        );
+        assert_eq!(
+            page.redirect_chain,
+            vec![format!("{}/old", base), format!("{}/moved", base)]
+        );
+        assert_eq!(page.attempts, 1);
+        assert!(page.error.is_none());
+    }
+
+    #[tokio::test]
+    async fn rejects_statuses_outside_accepted_classes() {
+        let base = site().await;
+        let url = format!("{}/missing", base);
+
+        let strict = spider(ScrapeParams::for_test(&url)).await;
+        let err = strict.scrape(url.clone()).await.unwrap_err();
+        assert!(matches!(err, AppError::HttpStatus { status: 404, .. }));
+        assert_eq!(strict.get_pages().await[0].status, Some(404));
+
+        let mut params = ScrapeParams::for_test(&url);
+        params.accepted_status_classes = Some(vec![StatusClass::Success, StatusClass::ClientError]);
+        let lenient = spider(params).await;
+        let (items, _) = lenient.scrape(url).await.unwrap();
+        assert_eq!(items[0].html, "not here");
+    }
+
+    #[tokio::test]
+    async fn serves_and_revalidates_cached_pages() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let base = serve(move |request| {
+            if request.path == "/robots.txt" {
+                return TestResponse::status(404);
+            }
+            counter.fetch_add(1, Ordering::SeqCst);
+            match request.headers.get("if-none-match").map(String::as_str) {
+                Some("\"v1\"") => TestResponse::status(304),
+                _ => TestResponse::ok("<html><body>cached</body></html>")
+                    .with_header("ETag", "\"v1\""),
+            }
+        })
+        .await;
+        let url = format!("{}/page", base);
+        let dir = std::env::temp_dir().join(format!("spider-cache-{}", uuid::Uuid::new_v4()));
+
+        let crawl = |mode: CacheMode, ttl_secs: u64, url: &str| {
+            let mut params = ScrapeParams::for_test(url);
+            params.cache.mode = mode;
+            params.cache.ttl_secs = ttl_secs;
+            let cache = HttpCache::new(&dir);
+            let url = url.to_string();
+            async move {
+                let mut spider = spider(params).await;
+                spider.http_cache = Some(cache);
+                let scraped = spider.scrape(url).await;
+                (scraped, spider.get_pages().await.remove(0))
+            }
+        };
+
+        let (scraped, page) = crawl(CacheMode::On, 0, &url).await;
+        assert_eq!(scraped.unwrap().0[0].html, "cached");
+        assert_eq!(page.cache, Some(CacheStatus::Miss));
+
+        let (scraped, page) = crawl(CacheMode::On, 0, &url).await;
+        assert_eq!(scraped.unwrap().0[0].html, "cached");
+        assert_eq!(page.cache, Some(CacheStatus::Revalidated));
+        assert_eq!(page.status, Some(200));
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        let (_, page) = crawl(CacheMode::On, 3600, &url).await;
+        assert_eq!(page.cache, Some(CacheStatus::Hit));
+        let (_, page) = crawl(CacheMode::Offline, 0, &url).await;
+        assert_eq!(page.cache, Some(CacheStatus::Hit));
+        let (scraped, _) = crawl(CacheMode::Offline, 0, &format!("{}/other", base)).await;
+        assert!(matches!(scraped, Err(AppError::CacheMiss(_))));
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn replays_recorded_crawls_without_the_network() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let base = serve(move |request| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            match request.path.as_str() {
+                "/robots.txt" => TestResponse::ok("User-agent: *\nDisallow: /private"),
+                "/old" => TestResponse::status(301).with_header("Location", "/new"),
+                _ => TestResponse::ok("<html><body>recorded</body></html>"),
+            }
+        })
+        .await;
+        let path = std::env::temp_dir().join(format!("spider-{}.warc", uuid::Uuid::new_v4()));
+
+        let crawl = |warc: Warc, path: &'static str| {
+            let warc = Arc::new(warc);
+            let params = ScrapeParams::for_test(&format!("{}/old", base));
+            let url = format!("{}{}", base, path);
+            async move {
+                let mut spider = spider(params).await;
+                spider.robots = RobotsCache::new(Client::new(), "scrapy").with_warc(warc.clone());
+                spider.warc = Some(warc);
+                let scraped = spider.scrape(url).await;
+                (
+                    scraped,
+                    spider.get_pages().await,
+                    spider.get_skipped().await,
+                )
+            }
+        };
+
+        let (scraped, pages, _) = crawl(Warc::record(&path).await.unwrap(), "/old").await;
+        assert_eq!(scraped.unwrap().0[0].html, "recorded");
+        assert_eq!(pages[0].redirect_chain.len(), 1);
+        let recorded_requests = requests.load(Ordering::SeqCst);
+
+        let (scraped, pages, _) = crawl(Warc::replay(&path).await.unwrap(), "/old").await;
+        assert_eq!(scraped.unwrap().0[0].html, "recorded");
+        assert_eq!(pages[0].status, Some(200));
+        assert_eq!(pages[0].redirect_chain, vec![format!("{}/old", base)]);
+
+        let (scraped, _, skipped) = crawl(Warc::replay(&path).await.unwrap(), "/private").await;
+        assert!(scraped.unwrap().0.is_empty());
+        assert_eq!(skipped.len(), 1);
+        let (scraped, _, _) = crawl(Warc::replay(&path).await.unwrap(), "/unseen").await;
+        assert!(matches!(scraped, Err(AppError::NotRecorded(_))));
+
+        assert_eq!(requests.load(Ordering::SeqCst), recorded_requests);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn keeps_the_pagination_chain_when_links_reach_pages_first() {
+        let base = serve(|request| {
+            let body = match request.path.as_str() {
+                "/p1" => r#"<a rel="next" href="/p2">2</a> <a href="/p3">3</a>"#,
+                "/p2" => r#"<a rel="next" href="/p3">3</a>"#,
+                "/p3" => r#"<a rel="next" href="/p4">4</a>"#,
+                "/p4" => "last",
+                _ => return TestResponse::status(404),
+            };
+            TestResponse::ok(&format!("<html><body>{}</body></html>", body))
+        })
+        .await;
+        let page = |path: &str| format!("{}{}", base, path);
+
+        let mut params = ScrapeParams::for_test(&page("/p1"));
+        params.follow_links = true;
+        params.enable_pagination = true;
+        let spider = spider(params).await;
+
+        let (_, links) = spider.scrape(page("/p1")).await.unwrap();
+        assert!(links.contains(&page("/p2")) && links.contains(&page("/p3")));
+        // Link discovery gets to /p3 before the chain does.
+        spider.scrape(page("/p3")).await.unwrap();
+        // /p2 only links to /p3, so /p4 comes from continuing the chain through /p3.
+        let (_, links) = spider.scrape(page("/p2")).await.unwrap();
+        assert_eq!(links, [page("/p3"), page("/p4")]);
+        spider.scrape(page("/p4")).await.unwrap();
+
+        assert_eq!(
+            spider.get_pagination_pages().await,
+            ["/p1", "/p2", "/p3", "/p4"].map(page)
+        );
+
+        // Resolving a next page with AI produces usage but no result for the page.
+        let resolution = UsageMetadata {
+            input_tokens: 100,
+            output_tokens: 10,
+            ..UsageMetadata::default()
+        };
+        spider.record_usage(&page("/p2"), &resolution).await;
+        let pagination = spider.report().await.pagination.unwrap();
+        assert_eq!(pagination.page_token_counts[1].input_tokens, 100);
+        assert_eq!(pagination.token_counts.output_tokens, 10);
+    }
+
+    #[tokio::test]
+    async fn extracts_with_selectors_without_ai() {
+        let base = serve(|_| {
+            TestResponse::ok("<html><body><h1>Lamp</h1><p class=\"price\">$20</p></body></html>")
+        })
+        .await;
+
+        let mut params = ScrapeParams::for_test(&format!("{}/", base));
+        params.enable_scraping = true;
+        params.extraction = Some(
+            serde_json::from_value(serde_json::json!({
+                "fields": {
+                    "name": { "selector": "h1" },
+                    "price": { "selector": ".price", "transforms": [{ "type": "number" }] },
+                },
+            }))
+            .unwrap(),
+        );
+        let mut disabled = params.clone();
+        disabled.enable_scraping = false;
+        let disabled = spider(disabled).await;
+        disabled.scrape(format!("{}/", base)).await.unwrap();
+        assert!(disabled.get_results().await.is_empty());
+
+        // No AI provider is configured, so any AI call would fail.
+        let spider = spider(params).await;
+
+        let (items, _) = spider.scrape(format!("{}/", base)).await.unwrap();
+        for item in items {
+            spider.process(item).await.unwrap();
+        }
+
+        let results = spider.get_results().await;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].model, SELECTOR_MODEL);
+        assert_eq!(
+            results[0].data,
+            serde_json::json!([{ "name": "Lamp", "price": 20 }])
+        );
+        assert_eq!(results[0].usage_metadata.total_cost, 0.0);
+    }
 }
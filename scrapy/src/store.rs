@@ -0,0 +1,601 @@
+//! Crawls, the pages they visited and the records they extracted, kept in SQLite so that
+//! results outlive the request that produced them.
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use rusqlite::types::{Type, Value as SqlValue};
+use rusqlite::{params, params_from_iter, Connection, Row};
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::models::{
+    CrawlReport, JobStatus, ScrapeParams, StoreQuery, StoredCrawl, StoredPage, StoredRecord,
+    UsageMetadata,
+};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS crawls (
+        id TEXT PRIMARY KEY,
+        url TEXT NOT NULL,
+        params TEXT NOT NULL,
+        status TEXT NOT NULL,
+        started_at TEXT NOT NULL,
+        finished_at TEXT,
+        usage TEXT,
+        budget_limited INTEGER NOT NULL DEFAULT 0,
+        error TEXT
+    );
+    CREATE TABLE IF NOT EXISTS pages (
+        crawl_id TEXT NOT NULL REFERENCES crawls (id),
+        url TEXT NOT NULL,
+        final_url TEXT,
+        status INTEGER,
+        attempts INTEGER NOT NULL,
+        error TEXT,
+        skipped TEXT,
+        redirect_chain TEXT NOT NULL,
+        usage TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS records (
+        id INTEGER PRIMARY KEY,
+        crawl_id TEXT NOT NULL REFERENCES crawls (id),
+        url TEXT NOT NULL,
+        data TEXT NOT NULL,
+        extracted_at TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS crawls_started_at ON crawls (started_at);
+    CREATE INDEX IF NOT EXISTS pages_crawl_id ON pages (crawl_id);
+    CREATE INDEX IF NOT EXISTS records_crawl_id ON records (crawl_id);
+    CREATE INDEX IF NOT EXISTS records_extracted_at ON records (extracted_at);
+";
+
+/// Rows returned by a list call when `StoreQuery::limit` is unset, and the most it may ask for.
+const DEFAULT_LIMIT: u32 = 100;
+const MAX_LIMIT: u32 = 1000;
+
+pub struct Store {
+    conn: Mutex<Connection>,
+}
+
+impl Store {
+    pub fn open(path: &Path) -> Result<Self, AppError> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        Self::init(Connection::open(path)?)
+    }
+
+    #[cfg(test)]
+    pub fn in_memory() -> Result<Self, AppError> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    /// The database at `STORE_PATH`, or `scrapy.db` when unset.
+    pub fn from_env() -> Result<Self, AppError> {
+        let path = env::var("STORE_PATH")
+            .ok()
+            .filter(|path| !path.is_empty())
+            .map_or_else(|| PathBuf::from("scrapy.db"), PathBuf::from);
+        Self::open(&path)
+    }
+
+    /// Runs `f` on a blocking thread, since every store call waits on SQLite.
+    pub async fn call<T, F>(self: &Arc<Self>, f: F) -> Result<T, AppError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Store) -> Result<T, AppError> + Send + 'static,
+    {
+        let store = self.clone();
+        tokio::task::spawn_blocking(move || f(&store))
+            .await
+            .map_err(std::io::Error::from)?
+    }
+
+    fn init(conn: Connection) -> Result<Self, AppError> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Saves a crawl that has just started. The API key is not stored.
+    pub fn start_crawl(
+        &self,
+        id: Uuid,
+        params: &ScrapeParams,
+        started_at: DateTime<Utc>,
+    ) -> Result<(), AppError> {
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO crawls (id, url, params, status, started_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                id.to_string(),
+                params.url,
+                serde_json::to_string(params)?,
+                status_text(JobStatus::Running)?,
+                time_text(started_at),
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Saves a crawl that was rejected before it started, with why.
+    pub fn fail_crawl(
+        &self,
+        id: Uuid,
+        params: &ScrapeParams,
+        failed_at: DateTime<Utc>,
+        error: &str,
+    ) -> Result<(), AppError> {
+        let failed_at = time_text(failed_at);
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO crawls (id, url, params, status, started_at, finished_at, error)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?5, ?6)",
+            params![
+                id.to_string(),
+                params.url,
+                serde_json::to_string(params)?,
+                status_text(JobStatus::Failed)?,
+                failed_at,
+                error,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Saves how a crawl ended, with every page it visited or skipped and its records.
+    /// A result whose data is not an array is saved as a single record.
+    pub fn finish_crawl(
+        &self,
+        id: Uuid,
+        status: JobStatus,
+        report: &CrawlReport,
+    ) -> Result<(), AppError> {
+        let crawl_id = id.to_string();
+        let usage = serde_json::to_string(&report.usage)?;
+
+        // Rows are serialized before taking the connection, so that reads only wait for
+        // the inserts themselves.
+        let mut pages = Vec::new();
+        for page in &report.pages {
+            pages.push(PageRow {
+                url: &page.url,
+                final_url: page.final_url.as_deref(),
+                status: page.status,
+                attempts: page.attempts,
+                error: page.error.as_deref(),
+                skipped: None,
+                redirect_chain: serde_json::to_string(&page.redirect_chain)?,
+                usage: serde_json::to_string(&page.usage)?,
+            });
+        }
+        let no_usage = serde_json::to_string(&UsageMetadata::default())?;
+        pages.extend(report.skipped.iter().map(|page| PageRow {
+            url: &page.url,
+            final_url: None,
+            status: None,
+            attempts: 0,
+            error: None,
+            skipped: Some(&page.reason),
+            redirect_chain: "[]".to_string(),
+            usage: no_usage.clone(),
+        }));
+
+        let mut records = Vec::new();
+        for result in &report.results {
+            let extracted_at = time_text(result.end_time.unwrap_or(result.start_time));
+            for record in result.records() {
+                records.push((&result.url, record.to_string(), extracted_at.clone()));
+            }
+        }
+
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        tx.execute(
+            "UPDATE crawls SET status = ?2, finished_at = ?3, usage = ?4, budget_limited = ?5
+             WHERE id = ?1",
+            params![
+                crawl_id,
+                status_text(status)?,
+                time_text(Utc::now()),
+                usage,
+                report.budget_limited,
+            ],
+        )?;
+
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO pages
+                 (crawl_id, url, final_url, status, attempts, error, skipped, redirect_chain, usage)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            )?;
+            for page in &pages {
+                insert.execute(params![
+                    crawl_id,
+                    page.url,
+                    page.final_url,
+                    page.status,
+                    page.attempts,
+                    page.error,
+                    page.skipped,
+                    page.redirect_chain,
+                    page.usage,
+                ])?;
+            }
+
+            let mut insert = tx.prepare(
+                "INSERT INTO records (crawl_id, url, data, extracted_at) VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for (url, data, extracted_at) in &records {
+                insert.execute(params![crawl_id, url, data, extracted_at])?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Crawls matching `query`, most recently started first.
+    pub fn crawls(&self, query: &StoreQuery) -> Result<Vec<StoredCrawl>, AppError> {
+        let filter = Filter::new(query, "id", "url", Some("started_at"))?;
+        let sql = format!(
+            "SELECT id, url, params, status, started_at, finished_at, usage, budget_limited, error,
+                 (SELECT COUNT(*) FROM pages WHERE pages.crawl_id = crawls.id),
+                 (SELECT COUNT(*) FROM records WHERE records.crawl_id = crawls.id)
+             FROM crawls{} ORDER BY started_at DESC, id LIMIT ? OFFSET ?",
+            filter.sql()
+        );
+
+        filter.query(&self.conn.lock().unwrap(), &sql, query, |row| {
+            Ok(StoredCrawl {
+                id: uuid(row, 0)?,
+                url: row.get(1)?,
+                params: json(row, 2)?,
+                status: parse(3, serde_json::from_value(row.get::<_, String>(3)?.into()))?,
+                started_at: time(row, 4)?,
+                finished_at: row
+                    .get::<_, Option<String>>(5)?
+                    .map(|_| time(row, 5))
+                    .transpose()?,
+                usage: row
+                    .get::<_, Option<String>>(6)?
+                    .map(|_| json(row, 6))
+                    .transpose()?,
+                budget_limited: row.get(7)?,
+                error: row.get(8)?,
+                pages: row.get(9)?,
+                records: row.get(10)?,
+            })
+        })
+    }
+
+    /// Pages matching `query` in the order they were saved; `since` and `until` do not apply.
+    pub fn pages(&self, query: &StoreQuery) -> Result<Vec<StoredPage>, AppError> {
+        let filter = Filter::new(query, "crawl_id", "url", None)?;
+        let sql = format!(
+            "SELECT crawl_id, url, final_url, status, attempts, error, skipped, redirect_chain,
+                 usage
+             FROM pages{} ORDER BY rowid LIMIT ? OFFSET ?",
+            filter.sql()
+        );
+
+        filter.query(&self.conn.lock().unwrap(), &sql, query, |row| {
+            Ok(StoredPage {
+                crawl_id: uuid(row, 0)?,
+                url: row.get(1)?,
+                final_url: row.get(2)?,
+                status: row.get(3)?,
+                attempts: row.get(4)?,
+                error: row.get(5)?,
+                skipped: row.get(6)?,
+                redirect_chain: json(row, 7)?,
+                usage: json(row, 8)?,
+            })
+        })
+    }
+
+    /// Records matching `query` in the order they were saved.
+    pub fn records(&self, query: &StoreQuery) -> Result<Vec<StoredRecord>, AppError> {
+        let filter = Filter::new(query, "crawl_id", "url", Some("extracted_at"))?;
+        let sql = format!(
+            "SELECT id, crawl_id, url, data, extracted_at
+             FROM records{} ORDER BY id LIMIT ? OFFSET ?",
+            filter.sql()
+        );
+
+        filter.query(&self.conn.lock().unwrap(), &sql, query, |row| {
+            Ok(StoredRecord {
+                id: row.get(0)?,
+                crawl_id: uuid(row, 1)?,
+                url: row.get(2)?,
+                data: json(row, 3)?,
+                extracted_at: time(row, 4)?,
+            })
+        })
+    }
+}
+
+/// A row of `pages`, serialized ahead of the insert.
+struct PageRow<'a> {
+    url: &'a str,
+    final_url: Option<&'a str>,
+    status: Option<u16>,
+    attempts: u32,
+    error: Option<&'a str>,
+    skipped: Option<&'a str>,
+    redirect_chain: String,
+    usage: String,
+}
+
+/// `WHERE` conditions built from a `StoreQuery`, with their bound values.
+struct Filter {
+    conditions: Vec<String>,
+    values: Vec<SqlValue>,
+}
+
+impl Filter {
+    fn new(
+        query: &StoreQuery,
+        crawl_column: &str,
+        url_column: &str,
+        time_column: Option<&str>,
+    ) -> Result<Self, AppError> {
+        let mut filter = Self {
+            conditions: vec![],
+            values: vec![],
+        };
+
+        if let Some(crawl) = query.crawl {
+            filter.push(format!("{} = ?", crawl_column), crawl.to_string());
+        }
+        if let Some(url) = &query.url {
+            filter.push(
+                format!("{} LIKE ? ESCAPE '\\'", url_column),
+                format!("{}%", escape_like(url)),
+            );
+        }
+        if let Some(column) = time_column {
+            if let Some(since) = &query.since {
+                filter.push(format!("{} >= ?", column), parse_time("since", since)?);
+            }
+            if let Some(until) = &query.until {
+                filter.push(format!("{} < ?", column), parse_time("until", until)?);
+            }
+        }
+
+        Ok(filter)
+    }
+
+    fn push(&mut self, condition: String, value: String) {
+        self.conditions.push(condition);
+        self.values.push(SqlValue::Text(value));
+    }
+
+    fn sql(&self) -> String {
+        if self.conditions.is_empty() {
+            String::new()
+        } else {
+            format!(" WHERE {}", self.conditions.join(" AND "))
+        }
+    }
+
+    /// Runs `sql`, which ends with the filter and then `LIMIT ? OFFSET ?`.
+    fn query<T>(
+        self,
+        conn: &Connection,
+        sql: &str,
+        query: &StoreQuery,
+        map: impl FnMut(&Row) -> rusqlite::Result<T>,
+    ) -> Result<Vec<T>, AppError> {
+        let mut values = self.values;
+        let limit = query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+        values.push(SqlValue::Integer(limit.into()));
+        values.push(SqlValue::Integer(query.offset.unwrap_or(0).into()));
+
+        let rows = conn
+            .prepare(sql)?
+            .query_map(params_from_iter(values), map)?
+            .collect::<Result<_, _>>()?;
+        Ok(rows)
+    }
+}
+
+/// Times are stored as RFC 3339 UTC text of a fixed width, so that they sort as text.
+fn time_text(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+fn parse_time(name: &str, value: &str) -> Result<String, AppError> {
+    let time = DateTime::parse_from_rfc3339(value)
+        .map_err(|e| AppError::InvalidParams(format!("{} '{}': {}", name, value, e)))?;
+    Ok(time_text(time.with_timezone(&Utc)))
+}
+
+fn status_text(status: JobStatus) -> Result<String, AppError> {
+    let status = serde_json::to_value(status)?;
+    Ok(status.as_str().unwrap_or_default().to_string())
+}
+
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+fn uuid(row: &Row, column: usize) -> rusqlite::Result<Uuid> {
+    parse(column, Uuid::parse_str(&row.get::<_, String>(column)?))
+}
+
+fn time(row: &Row, column: usize) -> rusqlite::Result<DateTime<Utc>> {
+    let time = DateTime::parse_from_rfc3339(&row.get::<_, String>(column)?);
+    parse(column, time.map(|time| time.with_timezone(&Utc)))
+}
+
+fn json<T: serde::de::DeserializeOwned>(row: &Row, column: usize) -> rusqlite::Result<T> {
+    parse(column, serde_json::from_str(&row.get::<_, String>(column)?))
+}
+
+fn parse<T, E>(column: usize, result: Result<T, E>) -> rusqlite::Result<T>
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    result.map_err(|e| rusqlite::Error::FromSqlConversionFailure(column, Type::Text, Box::new(e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::models::{AiScrapingResult, PageMetadata, SkippedPage};
+
+    fn report(url: &str, records: serde_json::Value, end_time: DateTime<Utc>) -> CrawlReport {
+        CrawlReport {
+            results: vec![AiScrapingResult {
+                url: url.to_string(),
+                model: "gemini-1.5-flash".to_string(),
+                start_time: end_time,
+                end_time: Some(end_time),
+                data: records,
+                usage_metadata: UsageMetadata::default(),
+                validation_errors: vec![],
+                content_size: None,
+            }],
+            pagination: None,
+            skipped: vec![SkippedPage {
+                url: format!("{}/private", url),
+                reason: "disallowed by robots.txt".to_string(),
+            }],
+            pages: vec![PageMetadata {
+                url: url.to_string(),
+                attempts: 2,
+                error: None,
+                final_url: Some(url.to_string()),
+                status: Some(200),
+                redirect_chain: vec![],
+                cache: None,
+                usage: UsageMetadata::default(),
+            }],
+            usage: UsageMetadata {
+                input_tokens: 10,
+                output_tokens: 5,
+                ..Default::default()
+            },
+            budget_limited: false,
+        }
+    }
+
+    #[test]
+    fn saves_crawls_and_filters_records() {
+        let store = Store::in_memory().unwrap();
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        let earlier = "2024-05-01T10:00:00Z".parse().unwrap();
+        let later = "2024-05-02T10:00:00Z".parse().unwrap();
+
+        let mut params = ScrapeParams::for_test("https://shop.example/lamps");
+        params.api_key = "secret".to_string();
+        store.start_crawl(first, &params, earlier).unwrap();
+        let records = json!([{ "name": "Lamp" }, { "name": "Desk_lamp" }]);
+        let report_one = report("https://shop.example/lamps", records, earlier);
+        store
+            .finish_crawl(first, JobStatus::Completed, &report_one)
+            .unwrap();
+
+        params.url = "https://blog.example/".to_string();
+        store.start_crawl(second, &params, later).unwrap();
+
+        let crawls = store.crawls(&StoreQuery::default()).unwrap();
+        assert_eq!(crawls.len(), 2);
+        assert_eq!(crawls[0].id, second);
+        assert_eq!(crawls[0].status, JobStatus::Running);
+        assert!(crawls[0].usage.is_none());
+        assert_eq!(crawls[1].status, JobStatus::Completed);
+        assert_eq!(crawls[1].usage.as_ref().unwrap().input_tokens, 10);
+        assert_eq!((crawls[1].pages, crawls[1].records), (2, 2));
+        assert_eq!(crawls[1].params["url"], "https://shop.example/lamps");
+        assert!(crawls[1].params.get("apiKey").is_none());
+
+        let query = |url: &str, since: Option<&str>| StoreQuery {
+            url: Some(url.to_string()),
+            since: since.map(str::to_string),
+            ..Default::default()
+        };
+        assert_eq!(
+            store.crawls(&query("https://blog", None)).unwrap()[0].id,
+            second
+        );
+        let since = Some("2024-05-01T13:00:00+02:00");
+        assert_eq!(store.crawls(&query("https://", since)).unwrap().len(), 1);
+
+        let records = store
+            .records(&StoreQuery {
+                crawl: Some(first),
+                offset: Some(1),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].data["name"], "Desk_lamp");
+        assert_eq!(records[0].extracted_at, earlier);
+        assert_eq!(
+            store.records(&query("https://shop", None)).unwrap().len(),
+            2
+        );
+        assert!(store
+            .records(&query("https://shop_", None))
+            .unwrap()
+            .is_empty());
+        assert!(store.records(&query("https://", since)).unwrap().is_empty());
+        assert!(matches!(
+            store.records(&query("https://", Some("yesterday"))),
+            Err(AppError::InvalidParams(_))
+        ));
+
+        let pages = store
+            .pages(&query("https://shop.example/lamps", None))
+            .unwrap();
+        assert_eq!(pages.len(), 2);
+        assert_eq!((pages[0].status, pages[0].attempts), (Some(200), 2));
+        assert_eq!(
+            pages[1].skipped.as_deref(),
+            Some("disallowed by robots.txt")
+        );
+    }
+
+    #[test]
+    fn saves_single_records_and_failed_crawls() {
+        let store = Store::in_memory().unwrap();
+        let (finished, rejected) = (Uuid::new_v4(), Uuid::new_v4());
+        let at = "2024-05-01T10:00:00Z".parse().unwrap();
+        let params = ScrapeParams::for_test("https://shop.example/");
+
+        store.start_crawl(finished, &params, at).unwrap();
+        let report = report("https://shop.example/", json!({ "title": "Shop" }), at);
+        store
+            .finish_crawl(finished, JobStatus::Completed, &report)
+            .unwrap();
+        store
+            .fail_crawl(rejected, &params, at, "Invalid parameters: bad selector")
+            .unwrap();
+
+        let crawl = |id: Uuid| StoreQuery {
+            crawl: Some(id),
+            ..Default::default()
+        };
+        let records = store.records(&crawl(finished)).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].data, json!({ "title": "Shop" }));
+
+        let failed = store.crawls(&crawl(rejected)).unwrap().remove(0);
+        assert_eq!(failed.status, JobStatus::Failed);
+        assert_eq!(failed.finished_at, Some(at));
+        assert_eq!(
+            failed.error.as_deref(),
+            Some("Invalid parameters: bad selector")
+        );
+        assert_eq!((failed.pages, failed.records), (0, 0));
+    }
+}
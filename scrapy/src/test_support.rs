@@ -0,0 +1,97 @@
+//! Local HTTP stand-in used by tests that exercise real fetches.
+
+use std::{collections::HashMap, sync::Arc};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+pub struct TestRequest {
+    pub path: String,
+    pub headers: HashMap<String, String>,
+}
+
+pub struct TestResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl TestResponse {
+    pub fn ok(body: &str) -> Self {
+        Self::status(200).with_body(body)
+    }
+
+    pub fn status(status: u16) -> Self {
+        Self {
+            status,
+            headers: vec![],
+            body: String::new(),
+        }
+    }
+
+    pub fn with_body(mut self, body: &str) -> Self {
+        self.body = body.to_string();
+        self
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+/// Serves every connection with `handler` and returns the server's base URL,
+/// e.g. `http://127.0.0.1:41234`.
+pub async fn serve<F>(handler: F) -> String
+where
+    F: Fn(&TestRequest) -> TestResponse + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handler = Arc::new(handler);
+
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let handler = handler.clone();
+            tokio::spawn(async move {
+                let mut buf = Vec::new();
+                let mut chunk = [0u8; 4096];
+                while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match socket.read(&mut chunk).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                    }
+                }
+
+                let head = String::from_utf8_lossy(&buf).to_string();
+                let mut lines = head.lines();
+                let path = lines
+                    .next()
+                    .and_then(|line| line.split_whitespace().nth(1))
+                    .unwrap_or("/")
+                    .to_string();
+                let headers = lines
+                    .filter_map(|line| line.split_once(':'))
+                    .map(|(k, v)| (k.trim().to_lowercase(), v.trim().to_string()))
+                    .collect();
+
+                let response = handler(&TestRequest { path, headers });
+
+                let mut raw = format!("HTTP/1.1 {} Test\r\n", response.status);
+                for (name, value) in &response.headers {
+                    raw.push_str(&format!("{}: {}\r\n", name, value));
+                }
+                raw.push_str(&format!(
+                    "Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    response.body.len(),
+                    response.body
+                ));
+                let _ = socket.write_all(raw.as_bytes()).await;
+            });
+        }
+    });
+
+    format!("http://{}", addr)
+}
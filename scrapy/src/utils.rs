@@ -1,16 +1,55 @@
-use std::{env, path::PathBuf};
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
 
-use crate::constants::PRICING_INFO;
+use crate::error::AppError;
+use crate::pricing;
 
-pub fn calculate_price(model: &str, input_tokens: u64, output_tokens: u64) -> f64 {
-    let pricing_info = PRICING_INFO.get(model).unwrap();
-    (input_tokens as f64 * pricing_info.input) + (output_tokens as f64 * pricing_info.output)
+pub fn calculate_price(
+    model: &str,
+    input_tokens: u64,
+    cached_input_tokens: u64,
+    output_tokens: u64,
+) -> Result<f64, AppError> {
+    let pricing_info =
+        pricing::get(model).ok_or_else(|| AppError::UnknownModel(model.to_string()))?;
+    Ok(pricing_info.cost(input_tokens, cached_input_tokens, output_tokens))
+}
+
+/// Like `calculate_price`, but `None` for models without pricing.
+pub fn price_of(
+    model: &str,
+    input_tokens: u64,
+    cached_input_tokens: u64,
+    output_tokens: u64,
+) -> Option<f64> {
+    calculate_price(model, input_tokens, cached_input_tokens, output_tokens).ok()
 }
 
 pub fn get_all_models() -> Vec<String> {
-    PRICING_INFO.keys().map(|k| k.to_string()).collect()
+    pricing::table().into_keys().collect()
+}
+
+/// Root of the on-disk caches: `CACHE_DIR`, or `.cache` when unset.
+pub fn cache_dir() -> PathBuf {
+    env::var("CACHE_DIR")
+        .ok()
+        .filter(|dir| !dir.is_empty())
+        .map_or_else(|| PathBuf::from(".cache"), PathBuf::from)
 }
 
+/// Writes `contents` beside `path` and renames it into place, so that concurrent
+/// readers never see a partial file.
+pub async fn write_atomically(path: &Path, contents: &[u8]) -> Result<(), AppError> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let partial = path.with_extension(format!("{}.tmp", uuid::Uuid::new_v4()));
+    tokio::fs::write(&partial, contents).await?;
+    tokio::fs::rename(&partial, path).await?;
+    Ok(())
+}
 
 pub fn find_static_dir() -> PathBuf {
     // 1. Try STATIC_DIR environment variable
@@ -0,0 +1,477 @@
+//! Recording a crawl's network traffic to a WARC file and replaying a crawl from one.
+//!
+//! Page and robots.txt fetches are stored as `request`/`response` record pairs. AI
+//! provider answers are stored as `resource` records whose target URI is
+//! `urn:scrapy:ai:<request hash>`, so that extraction replays along with the pages.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    path::{Component, Path, PathBuf},
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use reqwest::{
+    header::{HeaderMap, CONTENT_LENGTH, TRANSFER_ENCODING},
+    StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::Mutex,
+};
+use url::Url;
+use uuid::Uuid;
+
+use crate::{
+    error::AppError,
+    http_cache::{decode_body, CachedResponse},
+};
+
+/// Records a crawl to, or replays it from, a WARC file.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WarcOptions {
+    pub mode: WarcMode,
+    /// File name inside `WARC_DIR`; subdirectories are allowed, `..` is not.
+    pub file: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum WarcMode {
+    /// Fetch from the network and append every exchange to the file, creating it if
+    /// needed. Replaying a file recorded to several times serves the latest responses.
+    Record,
+    /// Serve every fetch and AI answer from the file, without touching the network.
+    Replay,
+}
+
+pub struct Warc {
+    state: State,
+}
+
+enum State {
+    Recording(Mutex<File>),
+    Replaying {
+        /// The last response recorded for each target URI.
+        responses: HashMap<String, CachedResponse>,
+        resources: HashMap<String, Vec<u8>>,
+    },
+}
+
+impl Warc {
+    /// Opens `options.file` inside `WARC_DIR`, which defaults to `warc`.
+    pub async fn open(options: &WarcOptions) -> Result<Self, AppError> {
+        let name = Path::new(&options.file);
+        let inside = name
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        if options.file.is_empty() || !inside {
+            return Err(AppError::InvalidParams(format!(
+                "warc file '{}' must be a relative path without '..'",
+                options.file
+            )));
+        }
+
+        let dir = env::var("WARC_DIR")
+            .ok()
+            .filter(|dir| !dir.is_empty())
+            .map_or_else(|| PathBuf::from("warc"), PathBuf::from);
+        let path = dir.join(name);
+        let opened = match options.mode {
+            WarcMode::Record => Self::record(&path).await,
+            WarcMode::Replay => Self::replay(&path).await,
+        };
+        opened.map_err(|e| AppError::InvalidParams(format!("warc file '{}': {}", options.file, e)))
+    }
+
+    /// Appends to `path`, so that an earlier recording is never lost.
+    pub async fn record(path: &Path) -> Result<Self, AppError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        let info = "software: scrapy/0.1\r\nformat: WARC File Format 1.1\r\n";
+        let file = Mutex::new(file);
+        append(
+            &file,
+            &record_bytes(
+                &[
+                    ("WARC-Type", "warcinfo".to_string()),
+                    ("Content-Type", "application/warc-fields".to_string()),
+                ],
+                info.as_bytes(),
+            ),
+        )
+        .await?;
+
+        Ok(Self {
+            state: State::Recording(file),
+        })
+    }
+
+    pub async fn replay(path: &Path) -> Result<Self, AppError> {
+        let contents = fs::read(path).await?;
+        let mut responses = HashMap::new();
+        let mut resources = HashMap::new();
+
+        for record in parse_records(&contents)? {
+            let Some(uri) = record.header("WARC-Target-URI") else {
+                continue;
+            };
+            match record.header("WARC-Type") {
+                Some("response") => {
+                    let fetched_at = record
+                        .header("WARC-Date")
+                        .and_then(|date| DateTime::parse_from_rfc3339(date).ok())
+                        .map_or_else(Utc::now, |date| date.with_timezone(&Utc));
+                    let response = parse_response(uri, fetched_at, record.block)?;
+                    responses.insert(uri.to_string(), response);
+                }
+                Some("resource") => {
+                    resources.insert(uri.to_string(), record.block.to_vec());
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            state: State::Replaying {
+                responses,
+                resources,
+            },
+        })
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        matches!(self.state, State::Replaying { .. })
+    }
+
+    /// The recorded response for `url`, when replaying.
+    pub fn response(&self, url: &Url) -> Option<CachedResponse> {
+        match &self.state {
+            State::Replaying { responses, .. } => responses.get(url.as_str()).cloned(),
+            State::Recording(_) => None,
+        }
+    }
+
+    /// The recorded resource for `uri`, when replaying.
+    pub fn resource(&self, uri: &str) -> Option<&[u8]> {
+        match &self.state {
+            State::Replaying { resources, .. } => resources.get(uri).map(Vec::as_slice),
+            State::Recording(_) => None,
+        }
+    }
+
+    /// Writes a GET request for `url` and the response to it, when recording. `body` is
+    /// stored as the client received it, with any chunking already undone, so
+    /// `Transfer-Encoding` is left out and `Content-Length` gives the stored length.
+    pub async fn record_response(
+        &self,
+        url: &Url,
+        status: u16,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Result<(), AppError> {
+        let State::Recording(file) = &self.state else {
+            return Ok(());
+        };
+
+        let request_id = record_id();
+        let mut path = url.path().to_string();
+        if let Some(query) = url.query() {
+            path = format!("{}?{}", path, query);
+        }
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+        let request = format!("GET {} HTTP/1.1\r\nhost: {}\r\n\r\n", path, host);
+
+        let reason = StatusCode::from_u16(status)
+            .ok()
+            .and_then(|s| s.canonical_reason())
+            .unwrap_or("");
+        let mut block = format!("HTTP/1.1 {} {}\r\n", status, reason).into_bytes();
+        for (name, value) in headers {
+            if name != TRANSFER_ENCODING && name != CONTENT_LENGTH {
+                block.extend_from_slice(name.as_str().as_bytes());
+                block.extend_from_slice(b": ");
+                block.extend_from_slice(value.as_bytes());
+                block.extend_from_slice(b"\r\n");
+            }
+        }
+        block.extend_from_slice(format!("content-length: {}\r\n\r\n", body.len()).as_bytes());
+        block.extend_from_slice(body);
+
+        let mut bytes = record_bytes(
+            &[
+                ("WARC-Type", "request".to_string()),
+                ("WARC-Record-ID", request_id.clone()),
+                ("WARC-Target-URI", url.to_string()),
+                (
+                    "Content-Type",
+                    "application/http;msgtype=request".to_string(),
+                ),
+            ],
+            request.as_bytes(),
+        );
+        bytes.extend(record_bytes(
+            &[
+                ("WARC-Type", "response".to_string()),
+                ("WARC-Target-URI", url.to_string()),
+                ("WARC-Concurrent-To", request_id),
+                (
+                    "Content-Type",
+                    "application/http;msgtype=response".to_string(),
+                ),
+            ],
+            &block,
+        ));
+
+        // One write per exchange keeps concurrent fetches from interleaving their records.
+        append(file, &bytes).await
+    }
+
+    /// Writes `content` as a resource record for `uri`, when recording.
+    pub async fn record_resource(
+        &self,
+        uri: &str,
+        content_type: &str,
+        content: &[u8],
+    ) -> Result<(), AppError> {
+        let State::Recording(file) = &self.state else {
+            return Ok(());
+        };
+        let bytes = record_bytes(
+            &[
+                ("WARC-Type", "resource".to_string()),
+                ("WARC-Target-URI", uri.to_string()),
+                ("Content-Type", content_type.to_string()),
+            ],
+            content,
+        );
+        append(file, &bytes).await
+    }
+}
+
+async fn append(file: &Mutex<File>, bytes: &[u8]) -> Result<(), AppError> {
+    let mut file = file.lock().await;
+    file.write_all(bytes).await?;
+    file.flush().await?;
+    Ok(())
+}
+
+fn record_id() -> String {
+    format!("<urn:uuid:{}>", Uuid::new_v4())
+}
+
+/// A complete record: the version line, `headers` with an ID and date added when missing,
+/// the block and the two line breaks that end every record.
+fn record_bytes(headers: &[(&str, String)], block: &[u8]) -> Vec<u8> {
+    let mut head = "WARC/1.1\r\n".to_string();
+    if !headers.iter().any(|(name, _)| *name == "WARC-Record-ID") {
+        head.push_str(&format!("WARC-Record-ID: {}\r\n", record_id()));
+    }
+    head.push_str(&format!(
+        "WARC-Date: {}\r\n",
+        Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+    ));
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str(&format!("Content-Length: {}\r\n\r\n", block.len()));
+
+    let mut bytes = head.into_bytes();
+    bytes.extend_from_slice(block);
+    bytes.extend_from_slice(b"\r\n\r\n");
+    bytes
+}
+
+struct Record<'a> {
+    headers: Vec<(String, String)>,
+    block: &'a [u8],
+}
+
+impl Record<'_> {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+fn parse_records(mut contents: &[u8]) -> Result<Vec<Record<'_>>, AppError> {
+    let malformed = |reason: &str| AppError::InvalidParams(format!("malformed WARC: {}", reason));
+    let mut records = Vec::new();
+
+    loop {
+        while let Some(rest) = contents.strip_prefix(b"\r\n") {
+            contents = rest;
+        }
+        if contents.is_empty() {
+            return Ok(records);
+        }
+
+        let (head, rest) = split_head(contents).ok_or_else(|| malformed("unterminated header"))?;
+        let mut lines = head.lines();
+        if !lines.next().is_some_and(|line| line.starts_with("WARC/")) {
+            return Err(malformed("missing version line"));
+        }
+        let headers: Vec<(String, String)> = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+            .collect();
+
+        let length = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("Content-Length"))
+            .and_then(|(_, value)| value.parse::<usize>().ok())
+            .ok_or_else(|| malformed("missing Content-Length"))?;
+        if rest.len() < length {
+            return Err(malformed("truncated record"));
+        }
+
+        records.push(Record {
+            headers,
+            block: &rest[..length],
+        });
+        contents = &rest[length..];
+    }
+}
+
+/// Splits a header section, ended by an empty line, from what follows it.
+fn split_head(bytes: &[u8]) -> Option<(String, &[u8])> {
+    let end = bytes.windows(4).position(|window| window == b"\r\n\r\n")?;
+    let head = String::from_utf8_lossy(&bytes[..end]).to_string();
+    Some((head, &bytes[end + 4..]))
+}
+
+fn parse_response(
+    url: &str,
+    fetched_at: DateTime<Utc>,
+    block: &[u8],
+) -> Result<CachedResponse, AppError> {
+    let malformed =
+        || AppError::InvalidParams(format!("malformed WARC: bad HTTP response for {}", url));
+    let (head, body) = split_head(block).ok_or_else(malformed)?;
+    let mut lines = head.lines();
+    let status = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(malformed)?;
+    let headers: BTreeMap<String, String> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+        .collect();
+    let body = decode_body(headers.get("content-type").map(String::as_str), body);
+
+    Ok(CachedResponse {
+        url: url.to_string(),
+        status,
+        headers,
+        fetched_at,
+        body,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderMap;
+
+    #[tokio::test]
+    async fn replays_what_was_recorded() {
+        let path = env::temp_dir().join(format!("crawl-{}.warc", Uuid::new_v4()));
+        let url = Url::parse("https://example.com/list?page=2").unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Type", "text/html".parse().unwrap());
+        let mut latin1 = headers.clone();
+        latin1.insert(
+            "Content-Type",
+            "text/html; charset=iso-8859-1".parse().unwrap(),
+        );
+        latin1.insert("Content-Length", "99".parse().unwrap());
+        latin1.insert("Transfer-Encoding", "chunked".parse().unwrap());
+        let cafe = Url::parse("https://example.com/caf%C3%A9").unwrap();
+
+        let recorder = Warc::record(&path).await.unwrap();
+        let record =
+            |url, status, headers, body| recorder.record_response(url, status, headers, body);
+        record(&url, 500, &headers, b"try again").await.unwrap();
+        record(&url, 200, &headers, b"<p>two\r\n\r\nlines</p>")
+            .await
+            .unwrap();
+        record(&cafe, 200, &latin1, b"caf\xe9").await.unwrap();
+        recorder
+            .record_resource(
+                "urn:scrapy:ai:abc",
+                "application/json",
+                b"{\"text\":\"[]\"}",
+            )
+            .await
+            .unwrap();
+        drop(recorder);
+
+        let written = String::from_utf8_lossy(&fs::read(&path).await.unwrap()).to_string();
+        assert!(written.starts_with("WARC/1.1\r\n"));
+        assert!(written.contains("GET /list?page=2 HTTP/1.1\r\nhost: example.com\r\n"));
+        assert!(!written.contains("transfer-encoding") && !written.contains("content-length: 99"));
+
+        let replay = Warc::replay(&path).await.unwrap();
+        assert!(replay.is_replaying());
+        let response = replay.response(&url).unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.header("content-type"), Some("text/html"));
+        assert_eq!(response.body, "<p>two\r\n\r\nlines</p>");
+        assert!(replay
+            .response(&Url::parse("https://example.com/").unwrap())
+            .is_none());
+        let response = replay.response(&cafe).unwrap();
+        assert_eq!(response.body, "café");
+        assert_eq!(response.header("content-length"), Some("4"));
+
+        // Recording again keeps what is already in the file.
+        let recorder = Warc::record(&path).await.unwrap();
+        recorder
+            .record_response(&url, 200, &headers, b"three")
+            .await
+            .unwrap();
+        drop(recorder);
+        let replay = Warc::replay(&path).await.unwrap();
+        fs::remove_file(&path).await.unwrap();
+        assert_eq!(replay.response(&url).unwrap().body, "three");
+        assert_eq!(replay.response(&cafe).unwrap().body, "café");
+        assert_eq!(
+            replay.resource("urn:scrapy:ai:abc"),
+            Some(&b"{\"text\":\"[]\"}"[..])
+        );
+    }
+
+    #[tokio::test]
+    async fn keeps_files_inside_the_warc_dir() {
+        let open = |file: &str| {
+            let options = WarcOptions {
+                mode: WarcMode::Replay,
+                file: file.to_string(),
+            };
+            async move { Warc::open(&options).await }
+        };
+
+        for file in ["", "../secret.warc", "/etc/passwd", "runs/../../x.warc"] {
+            assert!(
+                matches!(open(file).await, Err(AppError::InvalidParams(m)) if m.contains("relative")),
+                "{} was accepted",
+                file
+            );
+        }
+    }
+}
@@ -0,0 +1,175 @@
+//! XPath 1.0 over `scraper` documents.
+//!
+//! `scraper` has no XPath engine, so the HTML tree is mirrored into an `sxd-document`
+//! tree for `sxd-xpath` to query, and matched elements are mapped back to the HTML tree.
+//! Element and attribute names are mirrored without their namespace, so `//div/@class`
+//! matches as it would in a browser.
+
+use std::{cell::RefCell, collections::HashMap};
+
+use ego_tree::NodeId;
+use scraper::{ElementRef, Html, Node};
+use sxd_document::{dom, Package};
+use sxd_xpath::{nodeset, Context, Factory, Value, XPath};
+
+/// One result of an XPath expression.
+pub enum Match<'a> {
+    Element(ElementRef<'a>),
+    /// The string value of any other node, e.g. an attribute or a text node.
+    Text(String),
+    Number(f64),
+    Boolean(bool),
+}
+
+/// Checks that `expression` parses as XPath 1.0.
+pub fn validate(expression: &str) -> Result<(), String> {
+    compile(expression).map(drop)
+}
+
+fn compile(expression: &str) -> Result<XPath, String> {
+    Factory::new()
+        .build(expression)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "empty expression".to_string())
+}
+
+/// An HTML document mirrored for XPath queries.
+pub struct XPathDocument<'d> {
+    html: &'d Html,
+    to_xml: HashMap<NodeId, dom::Element<'d>>,
+    to_html: HashMap<dom::Element<'d>, NodeId>,
+    /// Compiled on first use; `None` for expressions that do not compile.
+    compiled: RefCell<HashMap<String, Option<XPath>>>,
+}
+
+impl<'d> XPathDocument<'d> {
+    // `dom::Element` hashes by address, which its interior mutability never changes.
+    #[allow(clippy::mutable_key_type)]
+    pub fn new(package: &'d Package, html: &'d Html) -> Self {
+        let document = package.as_document();
+        let mut to_xml = HashMap::new();
+        let mut to_html = HashMap::new();
+
+        // Children are pushed in reverse so that each parent receives them in order.
+        let mut pending = vec![(html.tree.root(), None::<dom::Element>)];
+        while let Some((node, parent)) = pending.pop() {
+            match (node.value(), parent) {
+                (Node::Document | Node::Fragment, _) => {
+                    pending.extend(node.children().rev().map(|child| (child, None)));
+                }
+                (Node::Element(element), parent) => {
+                    let mirror = document.create_element(element.name());
+                    for (name, value) in element.attrs() {
+                        mirror.set_attribute_value(name, value);
+                    }
+                    match parent {
+                        Some(parent) => parent.append_child(mirror),
+                        None => document.root().append_child(mirror),
+                    }
+                    to_xml.insert(node.id(), mirror);
+                    to_html.insert(mirror, node.id());
+                    pending.extend(node.children().rev().map(|child| (child, Some(mirror))));
+                }
+                (Node::Text(text), Some(parent)) => {
+                    parent.append_child(document.create_text(text));
+                }
+                (Node::Comment(comment), Some(parent)) => {
+                    parent.append_child(document.create_comment(comment));
+                }
+                _ => {}
+            }
+        }
+
+        Self {
+            html,
+            to_xml,
+            to_html,
+            compiled: RefCell::default(),
+        }
+    }
+
+    /// Evaluates `expression` with `scope` as the context node. Node-sets come back in
+    /// document order; an expression that fails to evaluate matches nothing.
+    pub fn select(&self, scope: ElementRef<'d>, expression: &str) -> Vec<Match<'d>> {
+        let mut compiled = self.compiled.borrow_mut();
+        let xpath = compiled
+            .entry(expression.to_string())
+            .or_insert_with(|| compile(expression).ok());
+        let (Some(xpath), Some(&context)) = (xpath, self.to_xml.get(&scope.id())) else {
+            return Vec::new();
+        };
+
+        match xpath.evaluate(&Context::new(), context) {
+            Ok(Value::Nodeset(nodes)) => nodes
+                .document_order()
+                .into_iter()
+                .map(|node| self.node_match(node))
+                .collect(),
+            Ok(Value::String(text)) => vec![Match::Text(text)],
+            Ok(Value::Number(number)) => vec![Match::Number(number)],
+            Ok(Value::Boolean(boolean)) => vec![Match::Boolean(boolean)],
+            Err(e) => {
+                log::debug!("XPath '{}' failed: {}", expression, e);
+                Vec::new()
+            }
+        }
+    }
+
+    fn node_match(&self, node: nodeset::Node<'d>) -> Match<'d> {
+        let element = match node {
+            nodeset::Node::Root(_) => Some(self.html.root_element()),
+            nodeset::Node::Element(element) => self
+                .to_html
+                .get(&element)
+                .and_then(|&id| self.html.tree.get(id))
+                .and_then(ElementRef::wrap),
+            _ => None,
+        };
+        element.map_or_else(|| Match::Text(node.string_value()), Match::Element)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selects_from_the_mirrored_tree() {
+        let html = Html::parse_document(
+            r#"<ul id="list"><li class="a">One <b>1</b></li><!-- gap --><li>Two</li></ul>"#,
+        );
+        let package = Package::new();
+        let document = XPathDocument::new(&package, &html);
+        let root = html.root_element();
+
+        let texts = |expression: &str| -> Vec<String> {
+            document
+                .select(root, expression)
+                .into_iter()
+                .map(|found| match found {
+                    Match::Element(element) => element.text().collect(),
+                    Match::Text(text) => text,
+                    Match::Number(number) => number.to_string(),
+                    Match::Boolean(boolean) => boolean.to_string(),
+                })
+                .collect()
+        };
+
+        assert_eq!(texts("//li"), ["One 1", "Two"]);
+        assert_eq!(texts("//li[last()]/text()"), ["Two"]);
+        assert_eq!(texts("//li[contains(@class, 'a')]/b"), ["1"]);
+        assert_eq!(texts("//ul/@id"), ["list"]);
+        assert_eq!(texts("count(//li)"), ["2"]);
+        assert_eq!(texts("//comment()"), [" gap "]);
+        assert!(texts("//li[").is_empty());
+
+        let item = match document.select(root, "//li[2]").pop() {
+            Some(Match::Element(item)) => item,
+            _ => panic!("no element"),
+        };
+        assert_eq!(item.inner_html(), "Two");
+        assert_eq!(document.select(item, "../@id").len(), 1);
+        assert!(validate("//li[").is_err());
+        assert!(validate("").is_err());
+    }
+}